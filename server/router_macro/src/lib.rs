@@ -19,6 +19,31 @@ fn get_function_name(attrs: &Vec<Attribute>) -> Option<String> {
     None
 }
 
+// Checks whether the type is a path ending with the specified name, e.g. "CancellationToken"
+fn is_named(typ: &Type, name: &str) -> bool {
+    if let Type::Path(path) = typ {
+        if let Some(segment) = path.path.segments.last() {
+            return segment.ident == name;
+        }
+    }
+    false
+}
+
+// Arguments of a routed function are either parsed from the params or supplied by the router
+enum ArgKind {
+    Param,
+    Token, // CancellationToken of the request
+    Snapshot // &Snapshot of the server state for handlers running on the worker pool
+}
+
+fn get_arg_kind(typ: &Type) -> ArgKind {
+    match typ {
+        Type::Reference(reference) if is_named(&reference.elem, "Snapshot") => ArgKind::Snapshot,
+        _ if is_named(typ, "CancellationToken") => ArgKind::Token,
+        _ => ArgKind::Param
+    }
+}
+
 #[proc_macro_attribute]
 pub fn route(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let ast: syn::Result<syn::ItemTrait> = syn::parse(item.clone());
@@ -30,14 +55,16 @@ pub fn route(_attr: TokenStream, item: TokenStream) -> TokenStream {
     
     struct FunctionArg {
         ident: Ident,
-        typ: Type
+        typ: Type,
+        kind: ArgKind
     }
 
     struct Function {
         rpc_method: String,
         ident: Ident,
         args: Vec<FunctionArg>,
-        has_return: bool // determines whether it's for a request or notification
+        has_return: bool, // determines whether it's for a request or notification
+        has_receiver: bool // functions without self only read a snapshot and run on the worker pool
     }
 
     let mut fns: Vec<Function> = Vec::new();
@@ -51,7 +78,8 @@ pub fn route(_attr: TokenStream, item: TokenStream) -> TokenStream {
             let function = function.unwrap();
 
             let mut function_args: Vec<FunctionArg> = Vec::new();
-            
+            let has_receiver = fn_item.sig.receiver().is_some();
+
             for arg in fn_item.sig.inputs { // iterate though all of the function's arguments
                 if let FnArg::Typed(typed) = arg {
                     let pat = *typed.pat;
@@ -60,12 +88,14 @@ pub fn route(_attr: TokenStream, item: TokenStream) -> TokenStream {
                         _ => panic!("Error") 
                     }.ident;
 
-                    function_args.push(FunctionArg { ident, typ: *typed.ty });
+                    let typ = *typed.ty;
+                    function_args.push(FunctionArg { ident, kind: get_arg_kind(&typ), typ });
                 }
             }
 
             fns.push(Function{ 
                 rpc_method: function,
+                has_receiver,
                 ident: fn_item.sig.ident, 
                 args: function_args,
                 has_return: matches!(fn_item.sig.output, ReturnType::Type(..))
@@ -76,33 +106,61 @@ pub fn route(_attr: TokenStream, item: TokenStream) -> TokenStream {
     // Constructs the match cases for each request
     //
     // "methodName" => {
-    //     struct Params {
-    //         param1: Type1, param2: Type2, ...
-    //     }
-    //     let params = serde_json::from_value::<Params>(request.params).expect("Error"); // parse the params
-    //     let result = router_inst.method_name(params.param1, params.param2, ...); // the actual routed function call
-    //     match (result) {
-    //         Ok(response) => Some(ResponseMessage{
-    //             jsonrpc: request.jsonrpc,
-    //             id: request.id,
-    //             result: Some(serde_json::to_value(response).expect("Error while serializing result!")),
-    //             error: None
-    //         }),
-    //         Err(error) => Some(ResponseMessage{
-    //             jsonrpc: request.jsonrpc,
-    //             id: request.id,
-    //             result: None,
-    //             error: Some(error)
-    //         })
-    //     }
+    //     let uri = document_uri(&request.params); // the document to resync if the handler panics
+    //     let token = router_inst.state().begin_request(&request.id, uri.clone()); // make the request cancellable, also by changes of the document
+    //     let response = {
+    //         struct Params {
+    //             param1: Type1, param2: Type2, ...
+    //         }
+    //         let result = match serde_path_to_error::deserialize::<_, Params>(request.params) { // parse the params
+    //             Ok(params) => match catch_unwind(|| router_inst.method_name(params.param1, token.clone(), ...)) { // the actual routed function call
+    //                 Ok(result) => result,
+    //                 Err(panic) => {
+    //                     router_inst.state().resync_document(uri);
+    //                     Err(ResponseError::internal_error(&request.method, panic))
+    //                 }
+    //             },
+    //             Err(error) => Err(ResponseError::invalid_params(&request.method, error))
+    //         };
+    //         if token.is_cancelled() {
+    //             ResponseMessage::error(request.id, ...) // REQUEST_CANCELLED or CONTENT_MODIFIED
+    //         } else {
+    //             match (result) {
+    //                 Ok(response) => ResponseMessage{
+    //                     jsonrpc: request.jsonrpc,
+    //                     id: request.id,
+    //                     result: Some(serde_json::to_value(response).expect("Error while serializing result!")),
+    //                     error: None
+    //                 },
+    //                 Err(error) => ResponseMessage{
+    //                     jsonrpc: request.jsonrpc,
+    //                     id: request.id,
+    //                     result: None,
+    //                     error: Some(error)
+    //                 }
+    //             }
+    //         }
+    //     };
+    //     router_inst.state().end_request(&response.id);
+    //     Some(response)
+    // }
+    //
+    // Functions without self get a snapshot instead and are executed on the worker pool, which sends
+    // the response itself:
+    //
+    // "methodName" => {
+    //     let token = router_inst.state().begin_request(&request.id, document_uri(&request.params));
+    //     router_inst.state().spawn(move |snapshot| {
+    //         ... // same as above, but calls R::method_name(snapshot, params.param1, ...)
+    //     });
+    //     None
     // }
     let request_match_cases_token = fns.iter().filter(|f| f.has_return).map(|f| {
         let rpc_method = &f.rpc_method;
         let function_name = &f.ident;
 
         // fields of the Params struct
-        //let mut fields = vec![quote! {}; 0];
-        let fields: Vec<proc_macro2::TokenStream> = f.args.iter().map(|arg| {
+        let fields: Vec<proc_macro2::TokenStream> = f.args.iter().filter(|arg| matches!(arg.kind, ArgKind::Param)).map(|arg| {
             let field_ident = &arg.ident;
             let field_type = &arg.typ;
             quote! {
@@ -110,32 +168,106 @@ pub fn route(_attr: TokenStream, item: TokenStream) -> TokenStream {
             }
         }).collect();
 
-        let field_names = f.args.iter().map(|arg| {
-            arg.ident.clone()
-        }).collect::<Vec<Ident>>();
+        // arguments of the routed function call
+        let call_args = f.args.iter().map(|arg| {
+            let field_ident = &arg.ident;
+            match arg.kind {
+                ArgKind::Param => quote! { params.#field_ident },
+                ArgKind::Token => quote! { token.clone() },
+                ArgKind::Snapshot => quote! { snapshot }
+            }
+        }).collect::<Vec<proc_macro2::TokenStream>>();
 
-        quote! {
-            #rpc_method => {
+        let function = if f.has_receiver {
+            quote! { router_inst.#function_name }
+        } else {
+            quote! { R::#function_name }
+        };
+
+        // a panicking handler is answered with INTERNAL_ERROR, handlers with self might have left
+        // the document they were working on in an inconsistent state
+        let on_panic = if f.has_receiver {
+            quote! { router_inst.state().resync_document(uri); }
+        } else {
+            quote! {}
+        };
+        let guarded_call = quote! {
+            match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| #function(#( #call_args ),*))) {
+                Ok(result) => result,
+                Err(panic) => {
+                    #on_panic
+                    Err(ResponseError::internal_error(&request.method, panic))
+                }
+            }
+        };
+
+        // requests like "shutdown" carry no params at all, so there is nothing to deserialize
+        let call = if fields.is_empty() {
+            quote! {
+                let result = #guarded_call;
+            }
+        } else {
+            quote! {
                 #[derive(Deserialize)]
                 #[serde(rename_all = "camelCase")]
                 struct Params {
                     #( #fields ),*
                 }
-                let params = serde_json::from_value::<Params>(request.params).expect("Error while deserializing params!");
-                let result = router_inst.#function_name(#( params.#field_names ),*);
+                // params of the wrong shape are answered with INVALID_PARAMS
+                let result = match serde_path_to_error::deserialize::<_, Params>(request.params) {
+                    Ok(params) => #guarded_call,
+                    Err(error) => Err(ResponseError::invalid_params(&request.method, error))
+                };
+            }
+        };
+
+        let respond = quote! {
+            #call
+            if token.is_cancelled() {
+                ResponseMessage {
+                    jsonrpc: request.jsonrpc,
+                    id: request.id,
+                    result: None,
+                    error: Some(token.error())
+                }
+            } else {
                 match (result) {
-                    Ok(response) => Some(ResponseMessage {
+                    Ok(response) => ResponseMessage {
                         jsonrpc: request.jsonrpc,
                         id: request.id,
                         result: Some(serde_json::to_value(response).expect("Error while serializing result!")),
                         error: None
-                    }),
-                    Err(error) => Some(ResponseMessage {
+                    },
+                    Err(error) => ResponseMessage {
                         jsonrpc: request.jsonrpc,
                         id: request.id,
                         result: None,
                         error: Some(error)
-                    })
+                    }
+                }
+            }
+        };
+
+        if f.has_receiver {
+            quote! {
+                #rpc_method => {
+                    let uri = document_uri(&request.params);
+                    let token = router_inst.state().begin_request(&request.id, uri.clone());
+                    let response = {
+                        #respond
+                    };
+                    router_inst.state().end_request(&response.id);
+                    Some(response)
+                }
+            }
+        } else {
+            quote! {
+                #rpc_method => {
+                    let token = router_inst.state().begin_request(&request.id, document_uri(&request.params));
+                    router_inst.state().spawn(move |snapshot| {
+                        #respond
+                    });
+                    None
                 }
             }
         }
@@ -147,8 +279,13 @@ pub fn route(_attr: TokenStream, item: TokenStream) -> TokenStream {
     //     struct Params {
     //         param1: Type1, param2: Type2, ...
     //     }
-    //     let params = serde_json::from_value::<Params>(request.params).expect("Error"); // parse the params
-    //     router_inst.method_name(params.param1, params.param2, ...); // the actual routed function call
+    //     let params = match serde_path_to_error::deserialize::<_, Params>(notification.params) { // parse the params
+    //         Ok(params) => params,
+    //         Err(error) => return None // logged and dropped
+    //     };
+    //     if let Err(panic) = catch_unwind(|| router_inst.method_name(params.param1, params.param2, ...)) { // the actual routed function call
+    //         router_inst.state().resync_document(uri);
+    //     }
     //     return None
     // }
     let notification_match_cases_token = fns.iter().filter(|f| !f.has_return).map(|f| {
@@ -168,15 +305,42 @@ pub fn route(_attr: TokenStream, item: TokenStream) -> TokenStream {
             arg.ident.clone()
         }).collect::<Vec<Ident>>();
 
-        quote! {
-            #rpc_method => {
+        // a panicking handler is logged, the document it was working on might be in an inconsistent state
+        let guarded_call = quote! {
+            if let Err(panic) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| router_inst.#function_name(#( params.#field_names ),*))) {
+                eprintln!("Notification {} failed: {}", notification.method, panic_message(panic.as_ref()));
+                router_inst.state().resync_document(uri);
+            }
+        };
+
+        // notifications like "exit" carry no params at all, so there is nothing to deserialize
+        let call = if f.args.is_empty() {
+            quote! {
+                #guarded_call
+            }
+        } else {
+            quote! {
                 #[derive(Deserialize)]
                 #[serde(rename_all = "camelCase")]
                 struct Params {
                     #( #fields ),*
                 }
-                let params = serde_json::from_value::<Params>(notification.params).expect("Error while deserializing params!");
-                router_inst.#function_name(#( params.#field_names ),*);
+                // notifications can't be answered, so ones with params of the wrong shape are dropped
+                let params = match serde_path_to_error::deserialize::<_, Params>(notification.params) {
+                    Ok(params) => params,
+                    Err(error) => {
+                        eprintln!("Dropping notification {} with invalid params at \"{}\": {}", notification.method, error.path(), error.inner());
+                        return None;
+                    }
+                };
+                #guarded_call
+            }
+        };
+
+        quote! {
+            #rpc_method => {
+                let uri = document_uri(&notification.params);
+                #call
                 return None;
             }
         }
@@ -186,7 +350,7 @@ pub fn route(_attr: TokenStream, item: TokenStream) -> TokenStream {
 
     // the actual route_msg function
     let route_fn = quote! {
-        fn route_msg(router_inst: &mut impl #router_ident, message: Message) -> Option<ResponseMessage> {
+        pub fn route_msg<R: #router_ident + 'static>(router_inst: &mut R, message: Message) -> Option<ResponseMessage> {
             match message {
                 Message::Request(request) => {
                     match request.method.as_str() {
@@ -212,8 +376,9 @@ pub fn route(_attr: TokenStream, item: TokenStream) -> TokenStream {
                         }
                     }
                 },
-                Message::Response(result) => {
-                    return None;
+                Message::Response(response) => {
+                    router_inst.state().handle_response(response);
+                    None
                 },
                 _ => {
                     eprintln!("Error while determining message type!");
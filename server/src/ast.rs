@@ -0,0 +1,188 @@
+use crate::{lexer::{Token, TokenKind}, syntax::{NodeId, SyntaxKind, SyntaxNode}};
+
+// Typed view of the syntax tree. The accessors return None for parts missing in half-typed code.
+macro_rules! ast_node {
+    ($name:ident) => {
+        #[derive(Debug, Clone, Copy)]
+        pub struct $name<'a>(&'a SyntaxNode);
+
+        impl<'a> $name<'a> {
+            pub fn cast(node: &'a SyntaxNode) -> Option<$name<'a>> {
+                (node.kind == SyntaxKind::$name).then_some($name(node))
+            }
+
+            pub fn syntax(self) -> &'a SyntaxNode {
+                self.0
+            }
+
+            pub fn id(self) -> NodeId {
+                self.0.id
+            }
+        }
+    };
+}
+
+ast_node!(SourceFile);
+ast_node!(FnDecl);
+ast_node!(StructDecl);
+ast_node!(StructField);
+ast_node!(GenericParam);
+ast_node!(Param);
+ast_node!(ExecAnnotation);
+ast_node!(Block);
+ast_node!(LetStmt);
+ast_node!(Name);
+ast_node!(NameRef);
+
+fn children<'a, N: 'a>(node: &'a SyntaxNode, cast: fn(&'a SyntaxNode) -> Option<N>) -> impl Iterator<Item = N> + 'a {
+    node.nodes().filter_map(cast)
+}
+
+pub enum Item<'a> {
+    Fn(FnDecl<'a>),
+    Struct(StructDecl<'a>)
+}
+
+impl<'a> SourceFile<'a> {
+    pub fn items(self) -> impl Iterator<Item = Item<'a>> {
+        self.0.nodes().filter_map(|node| match node.kind {
+            SyntaxKind::FnDecl => Some(Item::Fn(FnDecl(node))),
+            SyntaxKind::StructDecl => Some(Item::Struct(StructDecl(node))),
+            _ => None
+        })
+    }
+
+    pub fn functions(self) -> impl Iterator<Item = FnDecl<'a>> {
+        children(self.0, FnDecl::cast)
+    }
+
+    pub fn structs(self) -> impl Iterator<Item = StructDecl<'a>> {
+        children(self.0, StructDecl::cast)
+    }
+}
+
+impl<'a> FnDecl<'a> {
+    pub fn name(self) -> Option<Name<'a>> {
+        self.0.node(SyntaxKind::Name).map(Name)
+    }
+
+    pub fn generic_params(self) -> impl Iterator<Item = GenericParam<'a>> {
+        self.0.node(SyntaxKind::GenericParamList).into_iter().flat_map(|list| children(list, GenericParam::cast))
+    }
+
+    pub fn params(self) -> impl Iterator<Item = Param<'a>> {
+        self.0.node(SyntaxKind::ParamList).into_iter().flat_map(|list| children(list, Param::cast))
+    }
+
+    pub fn exec(self) -> Option<ExecAnnotation<'a>> {
+        self.0.node(SyntaxKind::ExecAnnotation).map(ExecAnnotation)
+    }
+
+    pub fn ret_type(self) -> Option<&'a SyntaxNode> {
+        self.0.node(SyntaxKind::RetType).and_then(|ret_type| ret_type.nodes().next())
+    }
+
+    pub fn body(self) -> Option<Block<'a>> {
+        self.0.node(SyntaxKind::Block).map(Block)
+    }
+}
+
+impl<'a> StructDecl<'a> {
+    pub fn name(self) -> Option<Name<'a>> {
+        self.0.node(SyntaxKind::Name).map(Name)
+    }
+
+    pub fn generic_params(self) -> impl Iterator<Item = GenericParam<'a>> {
+        self.0.node(SyntaxKind::GenericParamList).into_iter().flat_map(|list| children(list, GenericParam::cast))
+    }
+
+    pub fn fields(self) -> impl Iterator<Item = StructField<'a>> {
+        children(self.0, StructField::cast)
+    }
+}
+
+impl<'a> StructField<'a> {
+    pub fn name(self) -> Option<Name<'a>> {
+        self.0.node(SyntaxKind::Name).map(Name)
+    }
+
+    pub fn ty(self) -> Option<&'a SyntaxNode> {
+        self.0.nodes().find(|node| node.kind != SyntaxKind::Name)
+    }
+}
+
+impl<'a> GenericParam<'a> {
+    pub fn name(self) -> Option<Name<'a>> {
+        self.0.node(SyntaxKind::Name).map(Name)
+    }
+
+    // nat, mem, ty, dty or prv
+    pub fn kind(self) -> Option<TokenKind> {
+        self.0.tokens().map(|token| token.kind).find(|kind| matches!(kind, TokenKind::Nat | TokenKind::Mem | TokenKind::Ty | TokenKind::Dty | TokenKind::Prv))
+    }
+}
+
+impl<'a> Param<'a> {
+    pub fn name(self) -> Option<Name<'a>> {
+        self.0.node(SyntaxKind::Name).map(Name)
+    }
+
+    pub fn is_mut(self) -> bool {
+        self.0.token(TokenKind::Mut).is_some()
+    }
+
+    pub fn ty(self) -> Option<&'a SyntaxNode> {
+        self.0.nodes().find(|node| node.kind != SyntaxKind::Name)
+    }
+}
+
+impl<'a> ExecAnnotation<'a> {
+    pub fn name(self) -> Option<Name<'a>> {
+        self.0.node(SyntaxKind::Name).map(Name)
+    }
+
+    // The execution resource, like gpu.thread, and its generic arguments
+    pub fn exec_type(self) -> Option<&'a SyntaxNode> {
+        self.0.node(SyntaxKind::ExecType)
+    }
+}
+
+impl<'a> Block<'a> {
+    // Statements and the value of the block, which is an expression not wrapped in a statement
+    pub fn statements(self) -> impl Iterator<Item = &'a SyntaxNode> {
+        self.0.nodes().filter(|node| node.kind != SyntaxKind::Error)
+    }
+}
+
+impl<'a> LetStmt<'a> {
+    pub fn name(self) -> Option<Name<'a>> {
+        self.0.node(SyntaxKind::Name).map(Name)
+    }
+
+    pub fn is_mut(self) -> bool {
+        self.0.token(TokenKind::Mut).is_some()
+    }
+
+    // The type annotation comes before the initializer
+    pub fn ty(self) -> Option<&'a SyntaxNode> {
+        self.0.token(TokenKind::Colon)?;
+        self.0.nodes().find(|node| node.kind != SyntaxKind::Name)
+    }
+
+    pub fn initializer(self) -> Option<&'a SyntaxNode> {
+        let eq = self.0.token(TokenKind::Eq)?;
+        self.0.nodes().find(|node| node.span.start >= eq.span.end)
+    }
+}
+
+impl<'a> Name<'a> {
+    pub fn token(self) -> &'a Token {
+        self.0.tokens().next().expect("Names consist of an identifier")
+    }
+}
+
+impl<'a> NameRef<'a> {
+    pub fn token(self) -> &'a Token {
+        self.0.tokens().next().expect("Name references consist of an identifier")
+    }
+}
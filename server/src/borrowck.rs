@@ -0,0 +1,497 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::{lexer::TokenKind, resolver::{DefinitionId, DefinitionKind, Resolution}, syntax::{Depth, SyntaxKind, SyntaxNode, SyntaxTree}, typeck::{Ownership, Ty, Types}};
+
+// A step from a variable to a part of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Projection {
+    Field(String), // also the elements of tuples, like x.0
+    Index,
+    Select, // x[[thread]]
+    Deref,
+    View
+}
+
+// Memory that can be borrowed, a variable or a part of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Place {
+    pub root: DefinitionId,
+    pub projections: Vec<Projection>
+}
+
+impl Place {
+    // Places overlap unless they go to different fields at some point. Indices aren't known before
+    // run time, so they are assumed to be the same.
+    pub fn overlaps(&self, other: &Place) -> bool {
+        self.root == other.root && self.projections.iter().zip(&other.projections).all(|projections| match projections {
+            (Projection::Field(a), Projection::Field(b)) => a == b,
+            _ => true
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Loan {
+    pub place: Place,
+    pub path: String, // the borrowed place as written, like (*vec)[0]
+    pub ownership: Ownership,
+    pub span: Range<usize>, // of the borrow expression
+    pub live: Range<usize>, // from the borrow to the last use of the reference
+    pub holder: Option<DefinitionId> // the variable the reference is stored in, None for temporaries
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BorrowError {
+    pub span: Range<usize>,
+    pub message: String,
+    pub code: &'static str, // "borrow-conflict", "use-after-move" or "borrow-outlives"
+    pub related: Option<(Range<usize>, String)> // the conflicting borrow, the move or the end of the borrowed variable
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Borrows {
+    pub loans: Vec<Loan>,
+    pub errors: Vec<BorrowError>
+}
+
+// Whether values of the type are copied instead of moved. References are reborrowed when they are
+// passed on, their aliasing is checked through the loans.
+fn is_copy(ty: &Ty) -> bool {
+    match ty {
+        Ty::Array(element, _) | Ty::View(element, _) => is_copy(element),
+        Ty::Tuple(elements) => elements.iter().all(is_copy),
+        Ty::At(..) | Ty::Struct(..) => false,
+        _ => true
+    }
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// Skips the parentheses around an expression
+fn unparenthesized(mut node: &SyntaxNode) -> &SyntaxNode {
+    while node.kind == SyntaxKind::ParenExpr {
+        match node.nodes().next() {
+            Some(inner) => node = inner,
+            None => break
+        }
+    }
+    node
+}
+
+struct Checker<'t> {
+    tree: &'t SyntaxTree,
+    resolution: &'t Resolution,
+    types: &'t Types,
+    last_use: HashMap<DefinitionId, usize>, // end of the last reference to each definition
+    borrows: Borrows,
+    moved: HashMap<DefinitionId, Range<usize>>, // variables moved out of and where
+    statement_end: usize, // temporary references live until the end of the statement
+    holder: Option<DefinitionId>, // the variable the value of the current expression is stored in
+    depth: Depth // of the expressions being checked, the type checker reports the ones nested too deeply
+}
+
+impl<'t> Checker<'t> {
+    fn text(&self, node: &SyntaxNode) -> String {
+        collapse(self.tree.text(&node.span))
+    }
+
+    fn error(&mut self, span: Range<usize>, code: &'static str, message: String, related: Option<(Range<usize>, String)>) {
+        self.borrows.errors.push(BorrowError { span, message, code, related });
+    }
+
+    fn variable(&self, node: &SyntaxNode) -> Option<DefinitionId> {
+        let definition = *self.resolution.references.get(&node.node(SyntaxKind::NameRef)?.id)?;
+        let kind = self.resolution.definition(definition).kind;
+        matches!(kind, DefinitionKind::Local | DefinitionKind::Param | DefinitionKind::LoopVariable).then_some(definition)
+    }
+
+    // Walks down to the variable, the projections are collected from the outermost one
+    fn place(&self, mut node: &SyntaxNode) -> Option<Place> {
+        let mut projections = Vec::new();
+        loop {
+            let projection = match node.kind {
+                SyntaxKind::PathExpr => {
+                    projections.reverse();
+                    return self.variable(node).map(|root| Place { root, projections });
+                },
+                SyntaxKind::ParenExpr => {
+                    node = node.nodes().next()?;
+                    continue;
+                },
+                SyntaxKind::FieldExpr => {
+                    let field = node.node(SyntaxKind::NameRef).map(|name_ref| name_ref.span.clone()).or_else(|| node.token(TokenKind::Integer).map(|index| index.span.clone()))?;
+                    Projection::Field(self.tree.text(&field).trim().to_string())
+                },
+                SyntaxKind::IndexExpr => Projection::Index,
+                SyntaxKind::SelectExpr => Projection::Select,
+                SyntaxKind::ViewExpr => Projection::View,
+                SyntaxKind::PrefixExpr if node.token(TokenKind::Star).is_some() => Projection::Deref,
+                _ => return None
+            };
+            projections.push(projection);
+            node = node.nodes().next()?;
+        }
+    }
+
+    // The loans alive at the offset that conflict with an access of the place, only the unique ones
+    // if it's a read
+    fn conflict(&self, place: &Place, offset: usize, write: bool) -> Option<&Loan> {
+        self.borrows.loans.iter().find(|loan| {
+            loan.span.end <= offset && offset < loan.live.end && loan.place.overlaps(place) && (write || loan.ownership == Ownership::Uniq)
+        })
+    }
+
+    fn check_moved(&mut self, node: &SyntaxNode, place: &Place, what: &str) {
+        if let Some(moved) = self.moved.get(&place.root) {
+            let name = &self.resolution.definition(place.root).name;
+            let related = Some((moved.clone(), String::from("Value moved here")));
+            self.error(node.span.clone(), "use-after-move", format!("{what} of moved value `{name}`"), related);
+        }
+    }
+
+    fn report_conflict(&mut self, node: &SyntaxNode, message: String, loan: Option<Loan>) {
+        if let Some(loan) = loan {
+            let related = Some((loan.span.clone(), format!("`{}` is borrowed here", loan.path)));
+            self.error(node.span.clone(), "borrow-conflict", message, related);
+        }
+    }
+
+    // Statements
+
+    fn block(&mut self, block: &SyntaxNode, body: bool) {
+        let (statement_end, holder) = (self.statement_end, self.holder.take());
+        let mut value = None;
+        for statement in block.nodes() {
+            self.statement_end = statement.span.end;
+            match statement.kind {
+                SyntaxKind::LetStmt => self.let_stmt(statement),
+                SyntaxKind::ExprStmt => {
+                    if let Some(expression) = statement.nodes().next() {
+                        self.expression(expression);
+                    }
+                },
+                SyntaxKind::Error => {},
+                _ => {
+                    self.expression(statement);
+                    value = Some(statement);
+                }
+            }
+        }
+        (self.statement_end, self.holder) = (statement_end, holder);
+
+        // a reference to a variable of the block can't be its value, the variable goes away with it
+        let Some(value) = value.map(unparenthesized).filter(|value| value.kind == SyntaxKind::BorrowExpr) else {
+            return;
+        };
+        let Some(place) = value.nodes().next().and_then(|place| self.place(place)).filter(|place| !place.projections.contains(&Projection::Deref)) else {
+            return;
+        };
+        let definition = self.resolution.definition(place.root);
+        let scope = &self.resolution.scope(definition.scope).span;
+        let local = block.span.start <= scope.start && scope.end <= block.span.end;
+        if local || (body && definition.kind == DefinitionKind::Param) {
+            self.outlives(value, place.root, block.span.end);
+        }
+    }
+
+    fn outlives(&mut self, borrow: &SyntaxNode, root: DefinitionId, end: usize) {
+        let name = self.resolution.definition(root).name.clone();
+        let related = Some((end.saturating_sub(1)..end, format!("`{name}` goes out of scope here while still borrowed")));
+        self.error(borrow.span.clone(), "borrow-outlives", format!("`{name}` does not live long enough"), related);
+    }
+
+    fn let_stmt(&mut self, node: &SyntaxNode) {
+        let Some(initializer) = node.token(TokenKind::Eq).and_then(|eq| node.nodes().find(|child| child.span.start >= eq.span.end)) else {
+            return;
+        };
+        self.holder = node.node(SyntaxKind::Name).and_then(|name| self.resolution.definitions_by_node.get(&name.id)).copied();
+        self.expression(initializer);
+        self.holder = None;
+    }
+
+    // Expressions
+
+    fn expression(&mut self, node: &SyntaxNode) {
+        if !self.depth.enter() {
+            return;
+        }
+        self.expression_of_kind(node);
+        self.depth.exit();
+    }
+
+    fn expression_of_kind(&mut self, node: &SyntaxNode) {
+        // only the references among the elements of a value are stored with it
+        let holder = self.holder.take();
+        match node.kind {
+            SyntaxKind::ParenExpr | SyntaxKind::TupleExpr | SyntaxKind::ArrayExpr => {
+                for element in node.nodes() {
+                    self.holder = holder;
+                    self.expression(element);
+                }
+            },
+            SyntaxKind::PathExpr => self.use_variable(node),
+            SyntaxKind::FieldExpr | SyntaxKind::IndexExpr | SyntaxKind::SelectExpr | SyntaxKind::ViewExpr | SyntaxKind::PrefixExpr => {
+                match self.place(node) {
+                    Some(place) => {
+                        self.place_operands(node);
+                        self.check_moved(node, &place, "Use");
+                        let loan = self.conflict(&place, node.span.start, false).cloned();
+                        self.report_conflict(node, format!("Cannot use `{}` because it is borrowed as unique", self.text(node)), loan);
+                    },
+                    None => self.children(node)
+                }
+            },
+            SyntaxKind::BorrowExpr => {
+                self.holder = holder;
+                self.borrow(node);
+            },
+            SyntaxKind::BinaryExpr if node.token(TokenKind::Eq).is_some() => self.assignment(node),
+            SyntaxKind::BinaryExpr if node.tokens().any(|token| matches!(token.kind, TokenKind::PlusEq | TokenKind::MinusEq | TokenKind::StarEq | TokenKind::SlashEq)) => {
+                let mut operands = node.nodes();
+                let (lhs, rhs) = (operands.next(), operands.next());
+                if let Some(rhs) = rhs {
+                    self.expression(rhs);
+                }
+                if let Some(lhs) = lhs {
+                    self.write(lhs, true);
+                }
+            },
+            SyntaxKind::IfExpr => self.branches(node, SyntaxKind::Block),
+            SyntaxKind::SplitExpr => self.branches(node, SyntaxKind::SplitArm),
+            SyntaxKind::Block => self.block(node, false),
+            _ => self.children(node)
+        }
+    }
+
+    fn children(&mut self, node: &SyntaxNode) {
+        for child in node.nodes() {
+            match child.kind {
+                SyntaxKind::Name | SyntaxKind::NameRef | SyntaxKind::GenericArgList | SyntaxKind::LaunchConfig | SyntaxKind::Error => {},
+                SyntaxKind::Block => self.block(child, false),
+                _ => self.expression(child)
+            }
+        }
+    }
+
+    // The expressions within a place, like the index of x[i], from the innermost one
+    fn place_operands(&mut self, mut node: &SyntaxNode) {
+        let mut operands = Vec::new();
+        while matches!(node.kind, SyntaxKind::ParenExpr | SyntaxKind::FieldExpr | SyntaxKind::ViewExpr | SyntaxKind::PrefixExpr | SyntaxKind::IndexExpr | SyntaxKind::SelectExpr) {
+            let mut children = node.nodes().filter(|child| !matches!(child.kind, SyntaxKind::NameRef | SyntaxKind::GenericArgList));
+            let Some(base) = children.next() else {
+                break;
+            };
+            operands.push(children.collect::<Vec<&SyntaxNode>>());
+            node = base;
+        }
+        for operand in operands.into_iter().rev().flatten() {
+            self.expression(operand);
+        }
+    }
+
+    // A variable used as a value is read, and moved if it can't be copied
+    fn use_variable(&mut self, node: &SyntaxNode) {
+        let Some(root) = self.variable(node) else {
+            return;
+        };
+        let place = Place { root, projections: Vec::new() };
+        let name = self.resolution.definition(root).name.clone();
+        self.check_moved(node, &place, "Use");
+        let copy = self.types.expressions.get(&node.id).is_none_or(is_copy);
+        if copy {
+            let loan = self.conflict(&place, node.span.start, false).cloned();
+            self.report_conflict(node, format!("Cannot use `{name}` because it is borrowed as unique"), loan);
+        } else {
+            let loan = self.conflict(&place, node.span.start, true).cloned();
+            self.report_conflict(node, format!("Cannot move out of `{name}` because it is borrowed"), loan);
+            self.moved.entry(root).or_insert(node.span.clone());
+        }
+    }
+
+    fn borrow(&mut self, node: &SyntaxNode) {
+        let holder = self.holder.take();
+        let Some(operand) = node.nodes().next() else {
+            return;
+        };
+        let Some(place) = self.place(operand) else {
+            self.expression(operand); // a temporary
+            return;
+        };
+        self.place_operands(operand);
+        self.check_moved(node, &place, "Borrow");
+
+        let path = self.text(operand);
+        let ownership = if node.token(TokenKind::Shrd).is_some() { Ownership::Shrd } else { Ownership::Uniq };
+        let loan = self.conflict(&place, node.span.start, ownership == Ownership::Uniq).cloned();
+        let message = match (ownership, loan.as_ref().map(|loan| loan.ownership)) {
+            (Ownership::Uniq, Some(Ownership::Uniq)) => format!("Cannot borrow `{path}` as unique more than once at a time"),
+            (Ownership::Uniq, _) => format!("Cannot borrow `{path}` as unique because it is also borrowed as shared"),
+            (Ownership::Shrd, _) => format!("Cannot borrow `{path}` as shared because it is also borrowed as unique")
+        };
+        self.report_conflict(node, message, loan);
+
+        let end = holder.and_then(|holder| self.last_use.get(&holder)).map_or(self.statement_end, |last_use| (*last_use).max(self.statement_end));
+        self.borrows.loans.push(Loan { place, path, ownership, span: node.span.clone(), live: node.span.start..end, holder });
+    }
+
+    fn assignment(&mut self, node: &SyntaxNode) {
+        let mut operands = node.nodes();
+        let (Some(lhs), rhs) = (operands.next(), operands.next()) else {
+            return;
+        };
+        // a reference assigned to a variable lives as long as the variable is used
+        let target = (unparenthesized(lhs).kind == SyntaxKind::PathExpr).then(|| self.variable(unparenthesized(lhs))).flatten();
+        if let Some(rhs) = rhs {
+            if let Some(target) = target {
+                for loan in self.borrows.loans.iter_mut().filter(|loan| loan.holder == Some(target)) {
+                    loan.live.end = loan.live.end.min(node.span.start);
+                }
+            }
+            self.holder = target;
+            self.expression(rhs);
+            self.holder = None;
+            if let Some(target) = target {
+                self.escapes(rhs, target);
+            }
+        }
+        self.write(lhs, false);
+    }
+
+    // A reference stored in a variable that's used after the borrowed one goes out of scope
+    fn escapes(&mut self, value: &SyntaxNode, target: DefinitionId) {
+        let value = unparenthesized(value);
+        let Some(place) = value.nodes().next().filter(|_| value.kind == SyntaxKind::BorrowExpr).and_then(|place| self.place(place)) else {
+            return;
+        };
+        if place.projections.contains(&Projection::Deref) {
+            return;
+        }
+        let (borrowed, target_scope) = (self.resolution.definition(place.root).scope, self.resolution.definition(target).scope);
+        let end = self.resolution.scope(borrowed).span.end;
+        let mut scope = self.resolution.scope(borrowed).parent;
+        let mut nested = false;
+        while let Some(parent) = scope {
+            nested |= parent == target_scope;
+            scope = self.resolution.scope(parent).parent;
+        }
+        if nested && self.last_use.get(&target).is_some_and(|last_use| *last_use > end) {
+            self.outlives(value, place.root, end);
+        }
+    }
+
+    fn write(&mut self, lhs: &SyntaxNode, read: bool) {
+        let Some(place) = self.place(lhs) else {
+            self.expression(lhs);
+            return;
+        };
+        self.place_operands(lhs);
+        if place.projections.is_empty() && !read {
+            self.moved.remove(&place.root); // assigned a new value
+        } else {
+            self.check_moved(lhs, &place, "Use");
+        }
+        let loan = self.conflict(&place, lhs.span.start, true).cloned();
+        self.report_conflict(lhs, format!("Cannot assign to `{}` because it is borrowed", self.text(lhs)), loan);
+    }
+
+    // Only one of the branches of an if or split moves, so what's moved afterwards is what's moved in any of them
+    fn branches(&mut self, node: &SyntaxNode, branch: SyntaxKind) {
+        let before = self.moved.clone();
+        let mut after = self.moved.clone();
+        for child in node.nodes() {
+            if child.kind == branch || (child.kind == SyntaxKind::IfExpr && branch == SyntaxKind::Block) {
+                self.moved = before.clone();
+                match child.kind {
+                    SyntaxKind::SplitArm => self.children(child),
+                    SyntaxKind::Block => self.block(child, false),
+                    _ => self.expression(child)
+                }
+                for (definition, span) in self.moved.drain() {
+                    after.entry(definition).or_insert(span);
+                }
+            } else if !matches!(child.kind, SyntaxKind::NameRef | SyntaxKind::Error) {
+                self.moved = after.clone();
+                self.expression(child);
+                after = self.moved.clone();
+            }
+        }
+        self.moved = after;
+    }
+}
+
+// Checks that the references of a type checked syntax tree don't alias mutable memory, that moved
+// values aren't used anymore and that references don't outlive what they borrow
+pub fn check(tree: &SyntaxTree, resolution: &Resolution, types: &Types) -> Borrows {
+    let mut last_use: HashMap<DefinitionId, usize> = HashMap::new();
+    for (node, definition) in &resolution.references {
+        if let Some(node) = tree.find(*node) {
+            let end = last_use.entry(*definition).or_default();
+            *end = (*end).max(node.span.end);
+        }
+    }
+    let mut checker = Checker { tree, resolution, types, last_use, borrows: Borrows::default(), moved: HashMap::new(), statement_end: 0, holder: None, depth: Depth::default() };
+    for function in tree.root.nodes().filter(|node| node.kind == SyntaxKind::FnDecl) {
+        checker.moved.clear();
+        if let Some(body) = function.node(SyntaxKind::Block) {
+            checker.block(body, true);
+        }
+    }
+    checker.borrows.errors.sort_by_key(|error| error.span.start);
+    checker.borrows
+}
+
+#[test]
+fn test_check() {
+    let text = "struct S { a: [i32; 4] }
+fn consume(s: S) {}
+fn f(x: [i32; 4], s: S) -> &shrd cpu.mem i32 {
+    let r = &shrd x;
+    let w = &uniq x[0];
+    *w = (*r)[1];
+    let unique = &uniq x;
+    (*unique)[0] = 1;
+    let t = (1, 2);
+    let p = &uniq t.0;
+    let q = &uniq t.1;
+    *p = *q;
+    if true { consume(s); } else { consume(s); }
+    let a = s.a;
+    let mut outer = &shrd x;
+    {
+        let inner = 1;
+        outer = &shrd inner;
+    }
+    let b = *outer;
+    let y = 1;
+    &shrd y
+}
+";
+    let tree = crate::parser::parse(text);
+    assert_eq!(tree.errors, vec![]);
+    let resolution = crate::resolver::resolve(&tree);
+    let types = crate::typeck::check(&tree, &resolution);
+    let borrows = check(&tree, &resolution, &types);
+    let errors: Vec<(&str, &str, Option<&str>)> = borrows.errors.iter()
+        .map(|error| (&text[error.span.clone()], error.message.as_str(), error.related.as_ref().map(|(span, _)| &text[span.clone()])))
+        .collect();
+    assert_eq!(errors, vec![
+        ("&uniq x[0]", "Cannot borrow `x[0]` as unique because it is also borrowed as shared", Some("&shrd x")),
+        ("s.a", "Use of moved value `s`", Some("s")),
+        ("&shrd inner", "`inner` does not live long enough", Some("}")),
+        ("&shrd y", "`y` does not live long enough", Some("}"))
+    ]);
+    assert_eq!(borrows.errors[1].related.as_ref().unwrap().0.start, text.find("consume(s)").unwrap() + 8);
+    assert_eq!(borrows.errors[3].related.as_ref().unwrap().0.end, text.len() - 1);
+}
+
+#[test]
+fn test_check_deep_nesting() {
+    let text = format!("fn f(x: [i32; 4]) {{ let a = x{}; let b = 1{}; }}\n", "[0]".repeat(100_000), " + 1".repeat(100_000));
+    let tree = crate::parser::parse(&text);
+    assert!(tree.errors.iter().all(|error| error.message == "Code is nested too deeply"));
+    let resolution = crate::resolver::resolve(&tree);
+    let types = crate::typeck::check(&tree, &resolution);
+    assert_eq!(check(&tree, &resolution, &types), Borrows::default());
+}
@@ -0,0 +1,94 @@
+use crate::{structures::{Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, DocumentDiagnosticReport, FullDocumentDiagnosticReport, Location, UnchangedDocumentDiagnosticReport}, TextDocument};
+
+// Name the client shows as the origin of our diagnostics
+pub const SOURCE: &str = "descend";
+
+// Problems of a document, in the order they appear in the text. At most max_number_of_problems are
+// reported, as the client gets sluggish with thousands of them in a badly broken file.
+pub fn diagnostics(uri: &str, document: &TextDocument, max_number_of_problems: usize) -> Vec<Diagnostic> {
+    let syntax = document.syntax();
+    let resolution = document.resolution();
+    let types = document.types();
+    let borrows = document.borrows();
+    let syntax_errors = syntax.errors.iter().map(|error| (&error.span, error.code, &error.message, &error.related));
+    let resolve_errors = resolution.errors.iter().map(|error| (&error.span, error.code, &error.message, &error.related));
+    let type_errors = types.errors.iter().map(|error| (&error.span, error.code, &error.message, &error.related));
+    let borrow_errors = borrows.errors.iter().map(|error| (&error.span, error.code, &error.message, &error.related));
+    let mut problems: Vec<_> = syntax_errors.chain(resolve_errors).chain(type_errors).chain(borrow_errors).collect();
+    problems.sort_by_key(|(span, ..)| span.start);
+
+    problems.into_iter().take(max_number_of_problems).map(|(span, code, message, related)| Diagnostic {
+        range: document.range(span),
+        severity: DiagnosticSeverity::ERROR,
+        code: String::from(code),
+        source: String::from(SOURCE),
+        message: message.clone(),
+        related_information: related.iter().map(|(span, message)| DiagnosticRelatedInformation {
+            location: Location { uri: uri.to_string(), range: document.range(span) },
+            message: message.clone()
+        }).collect()
+    }).collect()
+}
+
+// 64-bit FNV-1a, unlike std's DefaultHasher its algorithm is fixed
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+// Identifies the diagnostics of a document, they are the same as long as its content, the limit and the
+// version of the server are. The hash is fixed, so the ids stay valid across restarts of the server.
+pub fn result_id(document: &TextDocument, max_number_of_problems: usize) -> String {
+    let mut hash = fnv1a(0xcbf2_9ce4_8422_2325, env!("CARGO_PKG_VERSION").as_bytes());
+    for chunk in document.rope.chunks() {
+        hash = fnv1a(hash, chunk.as_bytes());
+    }
+    hash = fnv1a(hash, &(max_number_of_problems as u64).to_le_bytes());
+    format!("{hash:016x}")
+}
+
+// Diagnostics for the pull model, only computed if they differ from the ones the client already has
+pub fn report(uri: &str, document: &TextDocument, max_number_of_problems: usize, previous_result_id: Option<&str>) -> DocumentDiagnosticReport {
+    let result_id = result_id(document, max_number_of_problems);
+    if previous_result_id == Some(result_id.as_str()) {
+        return DocumentDiagnosticReport::Unchanged(UnchangedDocumentDiagnosticReport { result_id });
+    }
+    DocumentDiagnosticReport::Full(FullDocumentDiagnosticReport { result_id, items: diagnostics(uri, document, max_number_of_problems) })
+}
+
+#[test]
+fn test_diagnostics() {
+    use crate::structures::PositionEncodingKind;
+
+    let document = TextDocument::new("fn main() {\n    let x = ;\n    let y = $;\n", 1, PositionEncodingKind::Utf16);
+    let diagnostics = diagnostics("file:///main.desc", &document, 100);
+    let codes: Vec<&str> = diagnostics.iter().map(|diagnostic| diagnostic.code.as_str()).collect();
+    assert_eq!(codes, vec!["syntax-error", "invalid-token", "syntax-error"]);
+    assert_eq!((diagnostics[0].range.start.line, diagnostics[0].range.start.character), (1, 12));
+
+    // the missing '}' at the end points at the body's '{'
+    let unclosed = diagnostics.last().unwrap();
+    assert_eq!(unclosed.related_information[0].location.range.start.character, 10);
+
+    assert_eq!(self::diagnostics("file:///main.desc", &document, 1).len(), 1);
+}
+
+#[test]
+fn test_report() {
+    use crate::structures::PositionEncodingKind;
+
+    let document = TextDocument::new("fn main() {", 1, PositionEncodingKind::Utf16);
+    let DocumentDiagnosticReport::Full(full) = report("file:///main.desc", &document, 100, None) else {
+        panic!("Expected a full report");
+    };
+    assert_eq!(full.items.len(), 1);
+    assert!(matches!(report("file:///main.desc", &document, 100, Some(&full.result_id)), DocumentDiagnosticReport::Unchanged(_)));
+
+    // a different limit or content invalidates the result
+    assert!(matches!(report("file:///main.desc", &document, 1, Some(&full.result_id)), DocumentDiagnosticReport::Full(_)));
+    let fixed = TextDocument::new("fn main() {}", 2, PositionEncodingKind::Utf16);
+    assert_eq!(result_id(&fixed, 100), result_id(&TextDocument::new("fn main() {}", 3, PositionEncodingKind::Utf16), 100));
+
+    // the hash mustn't change, or the ids the client keeps across restarts become invalid
+    assert_eq!(fnv1a(0xcbf2_9ce4_8422_2325, b"a"), 0xaf63_dc4c_8601_ec8c);
+    assert!(matches!(report("file:///main.desc", &fixed, 100, Some(&full.result_id)), DocumentDiagnosticReport::Full(_)));
+}
@@ -0,0 +1,459 @@
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    // keywords
+    Fn,
+    Let,
+    Mut,
+    Const,
+    If,
+    Else,
+    For,
+    In,
+    While,
+    Sched,
+    Split,
+    Sync,
+    Uniq,
+    Shrd,
+    Unsafe,
+    Struct,
+    Where,
+    Indep,
+    Nat,
+    Mem,
+    Ty,
+    Dty,
+    Prv,
+    True,
+    False,
+
+    // memories and execution resources, lexed as a whole like "gpu.global"
+    CpuMem,
+    GpuGlobal,
+    GpuShared,
+    GpuLocal,
+    CpuThread,
+    GpuGrid,
+    GpuBlock,
+    GpuWarp,
+    GpuThread,
+
+    Ident,
+    Lifetime, // 'a, provenances of references
+    Integer,
+    Float,
+    Char,
+    String,
+
+    // punctuation
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Comma,
+    Semicolon,
+    Colon,
+    ColonColon,
+    Dot,
+    DotDot,
+    Arrow, // ->
+    FatArrow, // =>
+    Eq,
+    EqEq,
+    Ne,
+    Lt,
+    Le,
+    Gt, // ">>" is lexed as two of them, as it closes nested generics more often than it shifts
+    Ge,
+    Plus,
+    PlusEq,
+    Minus,
+    MinusEq,
+    Star,
+    StarEq,
+    Slash,
+    SlashEq,
+    Percent,
+    Amp,
+    AmpAmp,
+    Pipe,
+    PipePipe,
+    Caret,
+    Bang,
+    At,
+    Question,
+
+    // trivia, kept so the tokens cover the whole text
+    Whitespace,
+    LineComment,
+    BlockComment,
+
+    Error // characters that don't start any token
+}
+
+impl TokenKind {
+    pub fn is_trivia(self) -> bool {
+        matches!(self, TokenKind::Whitespace | TokenKind::LineComment | TokenKind::BlockComment)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Range<usize> // bytes of the text, see TextDocument::range for the positions
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub span: Range<usize>,
+    pub message: String
+}
+
+// The tokens cover the text without gaps, malformed input is turned into Error tokens or
+// tokens that end early, together with an error describing it
+#[derive(Debug, Default)]
+pub struct Lexed {
+    pub tokens: Vec<Token>,
+    pub errors: Vec<LexError>
+}
+
+fn keyword(ident: &str) -> Option<TokenKind> {
+    Some(match ident {
+        "fn" => TokenKind::Fn,
+        "let" => TokenKind::Let,
+        "mut" => TokenKind::Mut,
+        "const" => TokenKind::Const,
+        "if" => TokenKind::If,
+        "else" => TokenKind::Else,
+        "for" => TokenKind::For,
+        "in" => TokenKind::In,
+        "while" => TokenKind::While,
+        "sched" => TokenKind::Sched,
+        "split" => TokenKind::Split,
+        "sync" => TokenKind::Sync,
+        "uniq" => TokenKind::Uniq,
+        "shrd" => TokenKind::Shrd,
+        "unsafe" => TokenKind::Unsafe,
+        "struct" => TokenKind::Struct,
+        "where" => TokenKind::Where,
+        "indep" => TokenKind::Indep,
+        "nat" => TokenKind::Nat,
+        "mem" => TokenKind::Mem,
+        "ty" => TokenKind::Ty,
+        "dty" => TokenKind::Dty,
+        "prv" => TokenKind::Prv,
+        "true" => TokenKind::True,
+        "false" => TokenKind::False,
+        _ => return None
+    })
+}
+
+fn resource(path: &str) -> Option<TokenKind> {
+    Some(match path {
+        "cpu.mem" => TokenKind::CpuMem,
+        "gpu.global" => TokenKind::GpuGlobal,
+        "gpu.shared" => TokenKind::GpuShared,
+        "gpu.local" => TokenKind::GpuLocal,
+        "cpu.thread" => TokenKind::CpuThread,
+        "gpu.grid" => TokenKind::GpuGrid,
+        "gpu.block" => TokenKind::GpuBlock,
+        "gpu.warp" => TokenKind::GpuWarp,
+        "gpu.thread" => TokenKind::GpuThread,
+        _ => return None
+    })
+}
+
+fn punctuation(text: &str) -> Option<(TokenKind, usize)> {
+    let two = match text.get(..2) {
+        Some("::") => Some(TokenKind::ColonColon),
+        Some("..") => Some(TokenKind::DotDot),
+        Some("->") => Some(TokenKind::Arrow),
+        Some("=>") => Some(TokenKind::FatArrow),
+        Some("==") => Some(TokenKind::EqEq),
+        Some("!=") => Some(TokenKind::Ne),
+        Some("<=") => Some(TokenKind::Le),
+        Some(">=") => Some(TokenKind::Ge),
+        Some("+=") => Some(TokenKind::PlusEq),
+        Some("-=") => Some(TokenKind::MinusEq),
+        Some("*=") => Some(TokenKind::StarEq),
+        Some("/=") => Some(TokenKind::SlashEq),
+        Some("&&") => Some(TokenKind::AmpAmp),
+        Some("||") => Some(TokenKind::PipePipe),
+        _ => None
+    };
+    if let Some(kind) = two {
+        return Some((kind, 2));
+    }
+    let one = match text.chars().next()? {
+        '(' => TokenKind::LParen,
+        ')' => TokenKind::RParen,
+        '{' => TokenKind::LBrace,
+        '}' => TokenKind::RBrace,
+        '[' => TokenKind::LBracket,
+        ']' => TokenKind::RBracket,
+        ',' => TokenKind::Comma,
+        ';' => TokenKind::Semicolon,
+        ':' => TokenKind::Colon,
+        '.' => TokenKind::Dot,
+        '=' => TokenKind::Eq,
+        '<' => TokenKind::Lt,
+        '>' => TokenKind::Gt,
+        '+' => TokenKind::Plus,
+        '-' => TokenKind::Minus,
+        '*' => TokenKind::Star,
+        '/' => TokenKind::Slash,
+        '%' => TokenKind::Percent,
+        '&' => TokenKind::Amp,
+        '|' => TokenKind::Pipe,
+        '^' => TokenKind::Caret,
+        '!' => TokenKind::Bang,
+        '@' => TokenKind::At,
+        '?' => TokenKind::Question,
+        _ => return None
+    };
+    Some((one, 1))
+}
+
+fn is_ident_start(c: char) -> bool {
+    c == '_' || c.is_alphabetic()
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
+}
+
+struct Lexer<'a> {
+    text: &'a str,
+    position: usize,
+    lexed: Lexed
+}
+
+impl<'a> Lexer<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn peek(&self, n: usize) -> Option<char> {
+        self.rest().chars().nth(n)
+    }
+
+    // Advances while the characters match, returns the end
+    fn eat_while(&mut self, predicate: impl Fn(char) -> bool) -> usize {
+        let rest = self.rest();
+        let len = rest.find(|c| !predicate(c)).unwrap_or(rest.len());
+        self.position += len;
+        self.position
+    }
+
+    fn error(&mut self, span: Range<usize>, message: impl Into<String>) {
+        self.lexed.errors.push(LexError { span, message: message.into() });
+    }
+
+    // Advances over the token starting with c
+    fn next_kind(&mut self, c: char) -> TokenKind {
+        let start = self.position;
+
+        if c.is_whitespace() {
+            self.eat_while(char::is_whitespace);
+            return TokenKind::Whitespace;
+        }
+        if self.rest().starts_with("//") {
+            self.eat_while(|c| c != '\n' && c != '\r');
+            return TokenKind::LineComment;
+        }
+        if self.rest().starts_with("/*") {
+            return self.block_comment();
+        }
+        if is_ident_start(c) {
+            let end = self.eat_while(is_ident_continue);
+            let ident = &self.text[start..end];
+            if ident == "cpu" || ident == "gpu" {
+                if let Some(kind) = self.resource(start) {
+                    return kind;
+                }
+            }
+            return keyword(ident).unwrap_or(TokenKind::Ident);
+        }
+        if c.is_ascii_digit() {
+            return self.number();
+        }
+        if c == '\'' {
+            return self.quote();
+        }
+        if c == '"' {
+            return self.string();
+        }
+        if let Some((kind, len)) = punctuation(self.rest()) {
+            self.position += len;
+            return kind;
+        }
+
+        self.position += c.len_utf8();
+        self.error(start..self.position, format!("Unexpected character '{c}'"));
+        TokenKind::Error
+    }
+
+    // "cpu" or "gpu" followed by ".<name>" of a known resource, otherwise it's a plain identifier
+    fn resource(&mut self, start: usize) -> Option<TokenKind> {
+        let rest = self.rest().strip_prefix('.')?;
+        let len = rest.find(|c| !is_ident_continue(c)).unwrap_or(rest.len());
+        let kind = resource(&self.text[start..(self.position + 1 + len)])?;
+        self.position += 1 + len;
+        Some(kind)
+    }
+
+    fn block_comment(&mut self) -> TokenKind {
+        let start = self.position;
+        self.position += 2;
+        let mut depth = 1;
+        while depth > 0 {
+            let rest = self.rest();
+            if rest.is_empty() {
+                self.error(start..self.position, "Unterminated block comment");
+                break;
+            }
+            if rest.starts_with("/*") {
+                depth += 1;
+                self.position += 2;
+            } else if rest.starts_with("*/") {
+                depth -= 1;
+                self.position += 2;
+            } else {
+                self.position += rest.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        TokenKind::BlockComment
+    }
+
+    // 42, 1_024, 0.5, 1e-3, 2.0f32, with the suffix being part of the literal
+    fn number(&mut self) -> TokenKind {
+        let mut kind = TokenKind::Integer;
+        self.eat_while(|c| c.is_ascii_digit() || c == '_');
+        // "0..n" is a range, not a float
+        if self.peek(0) == Some('.') && self.peek(1).is_some_and(|c| c.is_ascii_digit()) {
+            kind = TokenKind::Float;
+            self.position += 1;
+            self.eat_while(|c| c.is_ascii_digit() || c == '_');
+        }
+        let exponent = match (self.peek(0), self.peek(1), self.peek(2)) {
+            (Some('e' | 'E'), Some('+' | '-'), Some(c)) if c.is_ascii_digit() => 2,
+            (Some('e' | 'E'), Some(c), _) if c.is_ascii_digit() => 1,
+            _ => 0
+        };
+        if exponent > 0 {
+            kind = TokenKind::Float;
+            self.position += exponent;
+            self.eat_while(|c| c.is_ascii_digit() || c == '_');
+        }
+        self.eat_while(is_ident_continue);
+        kind
+    }
+
+    // 'a is a lifetime, 'a' a character
+    fn quote(&mut self) -> TokenKind {
+        let start = self.position;
+        self.position += 1;
+        match (self.peek(0), self.peek(1)) {
+            (Some('\\'), _) => {
+                self.position += 1;
+                self.position += self.peek(0).map_or(0, char::len_utf8);
+                self.close_char(start)
+            },
+            (Some(c), Some('\'')) if c != '\n' && c != '\r' => {
+                self.position += c.len_utf8() + 1;
+                TokenKind::Char
+            },
+            (Some(c), _) if is_ident_start(c) => {
+                self.eat_while(is_ident_continue);
+                TokenKind::Lifetime
+            },
+            _ => {
+                self.error(start..self.position, "Expected a lifetime or character after '");
+                TokenKind::Error
+            }
+        }
+    }
+
+    fn close_char(&mut self, start: usize) -> TokenKind {
+        if self.peek(0) == Some('\'') {
+            self.position += 1;
+        } else {
+            self.error(start..self.position, "Unterminated character literal");
+        }
+        TokenKind::Char
+    }
+
+    // Strings end at the end of the line if they aren't closed, so the rest of the file isn't swallowed
+    fn string(&mut self) -> TokenKind {
+        let start = self.position;
+        self.position += 1;
+        loop {
+            match self.peek(0) {
+                Some('"') => {
+                    self.position += 1;
+                    break;
+                },
+                Some('\\') => {
+                    self.position += 1;
+                    match self.peek(0) {
+                        Some(c) if c != '\n' && c != '\r' => self.position += c.len_utf8(),
+                        _ => {}
+                    }
+                },
+                Some(c) if c != '\n' && c != '\r' => self.position += c.len_utf8(),
+                _ => {
+                    self.error(start..self.position, "Unterminated string literal");
+                    break;
+                }
+            }
+        }
+        TokenKind::String
+    }
+}
+
+pub fn lex(text: &str) -> Lexed {
+    let mut lexer = Lexer { text, position: 0, lexed: Lexed::default() };
+    while let Some(c) = lexer.peek(0) {
+        let start = lexer.position;
+        let kind = lexer.next_kind(c);
+        lexer.lexed.tokens.push(Token { kind, span: start..lexer.position });
+    }
+    lexer.lexed
+}
+
+#[test]
+fn test_lex() {
+    let kinds = |text: &str| lex(text).tokens.into_iter().map(|token| token.kind).filter(|kind| !kind.is_trivia()).collect::<Vec<TokenKind>>();
+
+    assert_eq!(kinds("fn scale<n: nat>(v: &uniq gpu.global [f64; n]) -[t: gpu.thread]-> () {}"), vec![
+        TokenKind::Fn, TokenKind::Ident, TokenKind::Lt, TokenKind::Ident, TokenKind::Colon, TokenKind::Nat, TokenKind::Gt,
+        TokenKind::LParen, TokenKind::Ident, TokenKind::Colon, TokenKind::Amp, TokenKind::Uniq, TokenKind::GpuGlobal,
+        TokenKind::LBracket, TokenKind::Ident, TokenKind::Semicolon, TokenKind::Ident, TokenKind::RBracket, TokenKind::RParen,
+        TokenKind::Minus, TokenKind::LBracket, TokenKind::Ident, TokenKind::Colon, TokenKind::GpuThread, TokenKind::RBracket, TokenKind::Arrow,
+        TokenKind::LParen, TokenKind::RParen, TokenKind::LBrace, TokenKind::RBrace
+    ]);
+    assert_eq!(kinds("let x = gpu.alloc; for i in 0..n {}"), vec![
+        TokenKind::Let, TokenKind::Ident, TokenKind::Eq, TokenKind::Ident, TokenKind::Dot, TokenKind::Ident, TokenKind::Semicolon,
+        TokenKind::For, TokenKind::Ident, TokenKind::In, TokenKind::Integer, TokenKind::DotDot, TokenKind::Ident, TokenKind::LBrace, TokenKind::RBrace
+    ]);
+    assert_eq!(kinds("1.5e-3f32 'r 'c' \"s\\\"\" // c\n/* /* */ */"), vec![
+        TokenKind::Float, TokenKind::Lifetime, TokenKind::Char, TokenKind::String
+    ]);
+
+    // malformed input still covers the whole text, the errors describe what's wrong
+    let text = "let § = \"open\nx /* never closed";
+    let lexed = lex(text);
+    assert_eq!(lexed.tokens.first().unwrap().span.start, 0);
+    assert_eq!(lexed.tokens.last().unwrap().span.end, text.len());
+    assert!(lexed.tokens.windows(2).all(|pair| pair[0].span.end == pair[1].span.start));
+    let messages: Vec<&str> = lexed.errors.iter().map(|error| error.message.as_str()).collect();
+    assert_eq!(messages, vec!["Unexpected character '§'", "Unterminated string literal", "Unterminated block comment"]);
+
+    let document = crate::TextDocument::new("fn\r\n  main", 0, crate::structures::PositionEncodingKind::Utf16);
+    let range = document.range(&lex(&document.text()).tokens[2].span);
+    assert_eq!((range.start.line, range.start.character, range.end.line, range.end.character), (1, 2, 1, 6));
+}
@@ -0,0 +1,1566 @@
+use std::{any::Any, collections::{HashMap, HashSet}, fmt::format, io::{BufRead, Read, Write}, sync::{atomic::{AtomicBool, Ordering}, mpsc::{channel, Sender}, Arc, Mutex, OnceLock}, thread::JoinHandle};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub mod ast;
+pub mod borrowck;
+pub mod diagnostics;
+pub mod lexer;
+pub mod parser;
+pub mod resolver;
+pub mod structures;
+pub mod syntax;
+pub mod transport;
+pub mod typeck;
+pub mod workers;
+pub mod workspace;
+use serde_json::Value;
+use structures::*;
+use borrowck::Borrows;
+use resolver::{Definition, Resolution};
+use syntax::SyntaxTree;
+use typeck::Types;
+use workers::WorkerPool;
+use workspace::Workspace;
+
+use router_macro::route;
+use ropey::Rope;
+
+// Raw message according to the LSP Base Protocol, consisting of a HTTP-like header and content part:
+//
+// Content-Length: 123
+// Content-Type: application/vscode-jsonrpc; charset=utf-8
+// 
+// { ... }
+//
+// Note that the Content-Type field is optional and \r\n is used for the line breaks.
+#[derive(Debug)]
+pub struct RawMessage {
+    pub content_length: usize,
+    pub content_type: String,
+    pub content: String
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseError {
+    pub code: i32,
+    pub message: String,
+    pub data: Option<Value>
+}
+
+#[allow(dead_code)]
+impl ResponseError {
+    const PARSE_ERROR: i32 = -32700;
+    const INVALID_REQUEST: i32 = -32600;
+    const METHOD_NOT_FOUND: i32 = -32601;
+    const INVALID_PARAMS: i32 = -32602;
+    const INTERNAL_ERROR: i32 = -32603;
+
+    const SERVER_NOT_INITIALIZED: i32 = -32002;
+    const UNKNOWN_ERROR_CODE: i32 = -32001;
+    const REQUEST_FAILED: i32 = -32802;
+    const SERVER_CANCELLED: i32 = -32802;
+    const CONTENT_MODIFIED: i32 = -32801;
+    const REQUEST_CANCELLED: i32 = -32800;
+}
+
+impl ResponseError {
+    // Error for params that couldn't be deserialized, the data contains the path to the offending value
+    pub fn invalid_params(method: &str, error: serde_path_to_error::Error<serde_json::Error>) -> ResponseError {
+        ResponseError {
+            code: ResponseError::INVALID_PARAMS,
+            message: format!("Invalid params for {method}: {}", error.inner()),
+            data: Some(serde_json::json!({ "path": error.path().to_string() }))
+        }
+    }
+
+    // Error for a handler that panicked
+    pub fn internal_error(method: &str, panic: Box<dyn Any + Send>) -> ResponseError {
+        let panic = panic_message(panic.as_ref());
+        ResponseError {
+            code: ResponseError::INTERNAL_ERROR,
+            message: format!("Request {method} failed: {panic}"),
+            data: Some(serde_json::json!({ "method": method, "panic": panic }))
+        }
+    }
+}
+
+// Extracts the message of a caught panic, which is a &str or String if it was raised by panic!
+pub fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        String::from("Unknown panic")
+    }
+}
+
+// Extracts the "textDocument.uri" of params, as most text document requests and notifications have it
+pub fn document_uri(params: &Value) -> Option<String> {
+    params.get("textDocument")?.get("uri")?.as_str().map(String::from)
+}
+
+// Request message according to the LSP
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestMessage {
+    pub jsonrpc: String,
+    pub id: Id,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value
+}
+
+// Response message according to the LSP
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseMessage {
+    pub jsonrpc: String,
+    pub id: Id,
+    pub result: Option<Value>,
+    pub error: Option<ResponseError>
+}
+
+// Notification message according to the LSP
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationMessage {
+    pub jsonrpc: String,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value
+}
+
+impl ResponseMessage {
+    fn error(id: Id, code: i32, message: String) -> ResponseMessage {
+        ResponseMessage {
+            jsonrpc: String::from("2.0"),
+            id,
+            result: None,
+            error: Some(ResponseError {
+                code,
+                message,
+                data: None
+            })
+        }
+    }
+}
+
+// Message base, the deserializer will pick the right one
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", untagged)]
+pub enum Message {
+    Request(RequestMessage),
+    Response(ResponseMessage),
+    Notification(NotificationMessage)
+}
+
+// Cooperative cancellation of a request, set by "$/cancelRequest".
+// Handlers that take a CancellationToken argument get it supplied by the router and should poll it
+// during long computations. Once a request is cancelled its result is discarded anyway.
+// Requests are cancelled by the client, or by the server when the document they work on changes.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    content_modified: Arc<AtomicBool>,
+    document: Option<String> // uri of the document the request works on
+}
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    // The result would refer to an outdated version of the document
+    pub fn modify(&self) {
+        self.content_modified.store(true, Ordering::Relaxed);
+        self.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    // The error a cancelled request is answered with
+    pub fn error(&self) -> ResponseError {
+        if self.content_modified.load(Ordering::Relaxed) {
+            ResponseError { code: ResponseError::CONTENT_MODIFIED, message: String::from("Document was modified during the request!"), data: None }
+        } else {
+            ResponseError { code: ResponseError::REQUEST_CANCELLED, message: String::from("Request was cancelled!"), data: None }
+        }
+    }
+
+    // Allows handlers to bail out with "token.check()?;"
+    pub fn check(&self) -> Result<(), ResponseError> {
+        if self.is_cancelled() {
+            Err(self.error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// Settings of the "DescendServer" section, as declared in the extension's package.json
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub max_number_of_problems: usize
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings { max_number_of_problems: 100 }
+    }
+}
+
+// Result of "initialize" request
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializeResult {
+    pub capabilities: ServerCapabilities,
+    pub server_info: ServerInfo
+}
+
+fn bind_by_ref<T, R>(mut f: impl FnMut(&T) -> R) -> impl FnMut(T) -> R {
+    move |x| f(&x)
+}
+
+// Everything that can go wrong while reading a message from the client
+#[derive(Debug)]
+pub enum FramingError {
+    Eof, // the client closed the stream
+    Io(std::io::Error),
+    MalformedHeader(String),
+    InvalidContentLength(String),
+    MissingContentLength,
+    UnsupportedCharset(String),
+    TruncatedContent { expected: usize, read: usize },
+    InvalidUtf8(std::string::FromUtf8Error),
+    InvalidJson(serde_json::Error),
+    InvalidMessage { id: Option<Id>, error: serde_json::Error } // valid JSON, but not a JSON-RPC message
+}
+
+impl std::fmt::Display for FramingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FramingError::Eof => write!(f, "Unexpected end of stream!"),
+            FramingError::Io(error) => write!(f, "Error while reading from stream: {error}"),
+            FramingError::MalformedHeader(line) => write!(f, "Header field needs to be of the form \"X: Y\", got \"{line}\"!"),
+            FramingError::InvalidContentLength(value) => write!(f, "Invalid Content-Length \"{value}\"!"),
+            FramingError::MissingContentLength => write!(f, "Header is missing the Content-Length field!"),
+            FramingError::UnsupportedCharset(charset) => write!(f, "Unsupported charset \"{charset}\", only utf-8 is supported!"),
+            FramingError::TruncatedContent { expected, read } => write!(f, "Expected {expected} bytes of content, but the stream ended after {read}!"),
+            FramingError::InvalidUtf8(error) => write!(f, "Content is not valid UTF-8: {error}"),
+            FramingError::InvalidJson(error) => write!(f, "Content is not valid JSON: {error}"),
+            FramingError::InvalidMessage { error, .. } => write!(f, "Content is not a valid message: {error}")
+        }
+    }
+}
+
+impl FramingError {
+    // Errors of the stream itself, the server can't receive any more messages after them
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, FramingError::Eof | FramingError::Io(_) | FramingError::TruncatedContent { .. })
+    }
+}
+
+// Reads raw messages from a stream. After a malformed header the reader skips everything up to the
+// next Content-Length field, so a single bad frame doesn't throw off all following messages.
+pub struct MessageReader<B: BufRead> {
+    buf: B,
+    resync: bool
+}
+
+// Splits a header field "X: Y" into its name and value, names are tokens as defined by RFC 7230
+fn parse_header_field(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once(':')?;
+    let is_token = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+    if is_token {
+        Some((name, value.trim()))
+    } else {
+        None
+    }
+}
+
+// Extracts the charset parameter of a Content-Type like "application/vscode-jsonrpc; charset=utf-8"
+fn parse_charset(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case("charset") {
+            Some(value.trim().trim_matches('"').to_ascii_lowercase())
+        } else {
+            None
+        }
+    })
+}
+
+impl<B: BufRead> MessageReader<B> {
+    pub fn new(buf: B) -> MessageReader<B> {
+        MessageReader { buf, resync: false }
+    }
+
+    // Reads the next raw message from the stream
+    pub fn read(&mut self) -> Result<RawMessage, FramingError> {
+        let mut message: RawMessage = RawMessage{ content_length: 0, content_type: String::new(), content: String::new() };
+        let mut content_length = None;
+        let mut read_any = false;
+
+        // repeatedly read all the header fields, which are of the form "X: Y\r\n"
+        // a blank line "\r\n" indicates the end of the header and the begin of the content
+        loop {
+            let mut header_field = Vec::new();
+            if self.buf.read_until(b'\n', &mut header_field).map_err(FramingError::Io)? == 0 {
+                return Err(FramingError::Eof);
+            }
+            let header_field = String::from_utf8_lossy(&header_field);
+            let mut header_field = header_field.trim_end_matches(['\r', '\n']);
+
+            // the rest of a bad frame may precede the next header on the same line, as the content isn't terminated by a line break
+            if self.resync || (!read_any && parse_header_field(header_field).is_none()) {
+                match header_field.find("Content-Length:") {
+                    Some(start) => {
+                        header_field = &header_field[start..];
+                        self.resync = false;
+                    },
+                    None if self.resync => continue,
+                    None => {}
+                }
+            }
+
+            if header_field.trim().is_empty() {
+                if read_any {
+                    break;
+                } else {
+                    continue;
+                }
+            }
+
+            let (header_field_name, header_field_value) = match parse_header_field(header_field) {
+                Some(header_field) => header_field,
+                None => {
+                    self.resync = true;
+                    return Err(FramingError::MalformedHeader(header_field.to_string()));
+                }
+            };
+
+            if header_field_name.eq_ignore_ascii_case("Content-Length") {
+                match header_field_value.parse() {
+                    Ok(value) => content_length = Some(value),
+                    Err(_) => {
+                        self.resync = true;
+                        return Err(FramingError::InvalidContentLength(header_field_value.to_string()));
+                    }
+                }
+            } else if header_field_name.eq_ignore_ascii_case("Content-Type") {
+                message.content_type = header_field_value.to_string();
+            } // other header fields are ignored, as allowed by the LSP
+            read_any = true;
+        }
+
+        message.content_length = match content_length {
+            Some(content_length) => content_length,
+            None => {
+                self.resync = true;
+                return Err(FramingError::MissingContentLength);
+            }
+        };
+
+        let mut content = Vec::with_capacity(message.content_length);
+        (&mut self.buf).take(message.content_length as u64).read_to_end(&mut content).map_err(FramingError::Io)?;
+        if content.len() < message.content_length {
+            return Err(FramingError::TruncatedContent { expected: message.content_length, read: content.len() });
+        }
+
+        // the content is skipped in any case, so the next message can be read regardless
+        if let Some(charset) = parse_charset(&message.content_type) {
+            if charset != "utf-8" && charset != "utf8" { // "utf8" is still accepted for backwards compatibility
+                return Err(FramingError::UnsupportedCharset(charset));
+            }
+        }
+        message.content = String::from_utf8(content).map_err(FramingError::InvalidUtf8)?;
+
+        Ok(message)
+    }
+}
+
+#[test]
+fn test_message_reader() {
+    let input = concat!(
+        "Content-Length: 2\r\n\r\n{}",
+        "Content-Lenght 5\r\n\r\n{\"a\":", // malformed header, the rest of the frame is skipped
+        "\"b\"}\r\nmore garbage\r\n",
+        "garbage Content-Length: 4\r\nX-Unknown: 1\r\nContent-Type: application/vscode-jsonrpc; charset=utf8\r\n\r\n[ 1]",
+        "Content-Length: 2\r\nContent-Type: application/vscode-jsonrpc; charset=latin1\r\n\r\n{}"
+    ).as_bytes();
+    let input = [input, b"Content-Length: 2\r\n\r\n\xff}", b"Content-Length: 10\r\n\r\n{}"].concat();
+    let mut reader = MessageReader::new(input.as_slice());
+
+    assert_eq!(reader.read().unwrap().content, "{}");
+    assert!(matches!(reader.read(), Err(FramingError::MalformedHeader(_))));
+    assert_eq!(reader.read().unwrap().content, "[ 1]");
+    assert!(matches!(reader.read(), Err(FramingError::UnsupportedCharset(charset)) if charset == "latin1"));
+    assert!(matches!(reader.read(), Err(FramingError::InvalidUtf8(_))));
+    assert!(matches!(reader.read(), Err(FramingError::TruncatedContent { expected: 10, read: 2 })));
+    assert!(matches!(reader.read(), Err(FramingError::Eof)));
+}
+
+impl RawMessage {
+    // Constructs a raw message from its content part
+    fn from(content: serde_json::Value) -> RawMessage {
+        let content = content.to_string();
+        RawMessage{ content_length: content.len(), content_type: String::new(), content }
+    }
+
+    // Writes a raw message to the specified buffer
+    fn write(&self, buf: &mut impl Write) -> Result<(), String> {
+        let content_length = self.content_length;
+        let content_type = &self.content_type;
+
+        buf.write_fmt(format_args!("Content-Length: {content_length}\r\n")).map_err(bind_by_ref(std::io::Error::to_string))?;
+        if !content_type.is_empty() {
+            buf.write_fmt(format_args!("Content-Type: {content_type}\r\n")).map_err(bind_by_ref(std::io::Error::to_string))?;
+        }
+        buf.write_fmt(format_args!("\r\n")).map_err(bind_by_ref(std::io::Error::to_string))?;
+        let content = self.content.to_string();
+        buf.write_fmt(format_args!("{content}")).map_err(bind_by_ref(std::io::Error::to_string))?;
+        Ok(())
+    }
+}
+
+impl Message {
+    // Construct a message from a raw message
+    fn from_raw(raw: &RawMessage) -> Result<Message, FramingError> {
+        let content = serde_json::from_str::<Value>(&raw.content).map_err(FramingError::InvalidJson)?;
+        let id = content.get("id").and_then(|id| serde_json::from_value::<Id>(id.clone()).ok());
+
+        // serde will automatically pick the correct message type
+        serde_json::from_value::<Message>(content).map_err(|error| FramingError::InvalidMessage { id, error })
+    }
+}
+
+// Represents a text document as a rope, so edits and line lookups stay logarithmic in the size of the document.
+// The lines keep their original terminators, only "\n", "\r\n" and "\r" end a line like the LSP defines it.
+#[derive(Debug, Clone)]
+pub struct TextDocument {
+    pub rope: Rope,
+    pub version: i32, // increases with every change
+    pub encoding: PositionEncodingKind, // how the characters of positions are counted
+    syntax: OnceLock<Arc<SyntaxTree>>, // parsed on first use, shared by the snapshots of this version
+    resolution: OnceLock<Arc<Resolution>>, // resolved on first use like the syntax tree
+    types: OnceLock<Arc<Types>>, // checked on first use as well
+    borrows: OnceLock<Arc<Borrows>>
+}
+
+// The syntax tree, resolution, types and borrows are derived from the text, so they don't take part in comparisons
+impl PartialEq for TextDocument {
+    fn eq(&self, other: &TextDocument) -> bool {
+        self.rope == other.rope && self.version == other.version && self.encoding == other.encoding
+    }
+}
+
+impl PositionEncodingKind {
+    // Picks the encoding that's cheapest for us among the ones the client supports, UTF-16 is mandatory
+    pub fn negotiate(supported: &[String]) -> PositionEncodingKind {
+        let supports = |encoding: &str| supported.iter().any(|supported| supported == encoding);
+        if supports("utf-8") {
+            PositionEncodingKind::Utf8 // the lines are stored as UTF-8, so no conversion is needed
+        } else if supports("utf-32") {
+            PositionEncodingKind::Utf32
+        } else {
+            PositionEncodingKind::Utf16
+        }
+    }
+
+    // Number of code units of the character in this encoding
+    fn len(self, c: char) -> usize {
+        match self {
+            PositionEncodingKind::Utf8 => c.len_utf8(),
+            PositionEncodingKind::Utf16 => c.len_utf16(),
+            PositionEncodingKind::Utf32 => 1
+        }
+    }
+}
+
+impl TextDocument {
+    pub fn new(text: &str, version: i32, encoding: PositionEncodingKind) -> TextDocument {
+        TextDocument { rope: Rope::from_str(text), version, encoding, syntax: OnceLock::new(), resolution: OnceLock::new(), types: OnceLock::new(), borrows: OnceLock::new() }
+    }
+
+    pub fn text(&self) -> String {
+        self.rope.to_string()
+    }
+
+    pub fn syntax(&self) -> Arc<SyntaxTree> {
+        self.syntax.get_or_init(|| Arc::new(parser::parse(&self.text()))).clone()
+    }
+
+    pub fn resolution(&self) -> Arc<Resolution> {
+        self.resolution.get_or_init(|| Arc::new(resolver::resolve(&self.syntax()))).clone()
+    }
+
+    pub fn types(&self) -> Arc<Types> {
+        self.types.get_or_init(|| Arc::new(typeck::check(&self.syntax(), &self.resolution()))).clone()
+    }
+
+    pub fn borrows(&self) -> Arc<Borrows> {
+        self.borrows.get_or_init(|| Arc::new(borrowck::check(&self.syntax(), &self.resolution(), &self.types()))).clone()
+    }
+
+    // The definition of the identifier at the position, if it's declared in this document
+    pub fn definition_at(&self, position: &Position) -> Option<Definition> {
+        let resolution = self.resolution();
+        let definition = resolution.definition_at(&self.syntax(), self.offset(position))?;
+        Some(resolution.definition(definition).clone())
+    }
+
+    // The names that can be used at the position, e.g. for completion
+    pub fn visible_at(&self, position: &Position) -> Vec<Definition> {
+        self.resolution().visible_at(self.offset(position)).into_iter().cloned().collect()
+    }
+
+    // Number of lines, including the empty last line after a final terminator
+    pub fn line_count(&self) -> u32 {
+        self.rope.len_lines() as u32
+    }
+
+    // The specified line without its terminator, lines beyond the end are empty
+    pub fn line(&self, line: u32) -> String {
+        if line >= self.line_count() {
+            return String::new();
+        }
+        let line = String::from(self.rope.line(line as usize));
+        let content = line.strip_suffix("\r\n")
+            .or_else(|| line.strip_suffix('\n'))
+            .or_else(|| line.strip_suffix('\r'))
+            .unwrap_or(&line);
+        content.to_string()
+    }
+
+    // Converts the character of a position into a byte index of its line. Characters beyond the end
+    // of the line refer to the end, ones in the middle of a character to its start.
+    pub fn byte_index(&self, position: &Position) -> usize {
+        let line = self.line(position.line);
+        let mut units = 0usize;
+        for (i, c) in line.char_indices() {
+            let next = units + self.encoding.len(c);
+            if next > position.character as usize {
+                return i;
+            }
+            units = next;
+        }
+        line.len()
+    }
+
+    // Converts a byte index of a line into a position, for everything we send to the client
+    pub fn position(&self, line: u32, byte_index: usize) -> Position {
+        let character = self.line(line)[..byte_index].chars().map(|c| self.encoding.len(c)).sum::<usize>();
+        Position { line, character: character as u32 }
+    }
+
+    // Converts a byte offset of the whole text, e.g. of a token, into a position
+    pub fn offset_position(&self, offset: usize) -> Position {
+        let offset = offset.min(self.rope.len_bytes());
+        let line = self.rope.byte_to_line(offset);
+        let line_start = self.rope.line_to_byte(line);
+        let byte_index = (offset - line_start).min(self.line(line as u32).len()); // offsets within a "\r\n" belong to the line's end
+        self.position(line as u32, byte_index)
+    }
+
+    // Lines beyond the end of the text refer to its end, like characters beyond the end of a line
+    fn clamp(&self, position: &Position) -> Position {
+        let last = self.line_count() - 1;
+        if position.line > last {
+            Position { line: last, character: u32::MAX }
+        } else {
+            *position
+        }
+    }
+
+    // Converts a position into a byte offset of the whole text, the inverse of offset_position
+    pub fn offset(&self, position: &Position) -> usize {
+        let position = self.clamp(position);
+        self.rope.line_to_byte(position.line as usize) + self.byte_index(&position)
+    }
+
+    pub fn range(&self, span: &std::ops::Range<usize>) -> Range {
+        Range { start: self.offset_position(span.start), end: self.offset_position(span.end) }
+    }
+
+    // Index of the position's character in the whole rope
+    fn char_index(&self, position: &Position) -> usize {
+        let position = self.clamp(position);
+        let line_start = self.rope.line_to_char(position.line as usize);
+        let line_start_byte = self.rope.char_to_byte(line_start);
+        self.rope.byte_to_char(line_start_byte + self.byte_index(&position))
+    }
+
+    // Erases the specified range
+    fn erase(&mut self, range: &Range) {
+        let start = self.char_index(&range.start);
+        let end = self.char_index(&range.end);
+        self.rope.remove(start..end);
+        self.invalidate();
+    }
+
+    // Inserts the specified text at specified position
+    fn insert(&mut self, position: &Position, text: &str) {
+        let index = self.char_index(position);
+        self.rope.insert(index, text);
+        self.invalidate();
+    }
+
+    // Drops what's derived from the text, it's computed again when it's used next
+    fn invalidate(&mut self) {
+        self.syntax = OnceLock::new();
+        self.resolution = OnceLock::new();
+        self.types = OnceLock::new();
+        self.borrows = OnceLock::new();
+    }
+
+    // Replaces specified range with specified text
+    fn edit(&mut self, range: &Range, text: &str) {
+        self.erase(range);
+        self.insert(&range.start, text);
+    }
+}
+
+#[test]
+fn test_erase() {
+    let mut content = TextDocument::new("01234\r\n56789\r\nabcde", 0, PositionEncodingKind::Utf16);
+    let match1 = TextDocument::new("012de", 0, PositionEncodingKind::Utf16);
+    let match2 = TextDocument::new("01e", 0, PositionEncodingKind::Utf16);
+    
+    content.erase(&Range {
+        start: Position {
+            line: 0,
+            character: 3
+        },
+        end: Position {
+            line: 2,
+            character: 3
+        }
+    });
+    assert_eq!(content, match1);
+    content.erase(&Range {
+        start: Position {
+            line: 0,
+            character: 2
+        },
+        end: Position {
+            line: 0,
+            character: 4
+        }
+    });
+    assert_eq!(content, match2);
+}
+
+#[test]
+fn test_insert() {
+    let mut content = TextDocument::new("01e", 0, PositionEncodingKind::Utf16);
+    let match1 = TextDocument::new("012de", 0, PositionEncodingKind::Utf16);
+    let match2 = TextDocument::new("01234\r\n56789\r\nabcde", 0, PositionEncodingKind::Utf16);
+    
+    content.insert(&Position {
+        line: 0,
+        character: 2
+    }, "2d");
+    assert_eq!(content, match1);
+    content.insert(&Position {
+        line: 0,
+        character: 3
+    }, "34\r\n56789\r\nabc");
+    assert_eq!(content, match2);
+}
+
+#[test]
+fn test_line_endings() {
+    let lines = |content: &TextDocument| content.rope.lines().map(String::from).collect::<Vec<String>>();
+    let mut content = TextDocument::new("ab\ncd\r\nef\rgh", 0, PositionEncodingKind::Utf16);
+    assert_eq!(lines(&content), vec!["ab\n", "cd\r\n", "ef\r", "gh"]);
+    assert_eq!(content.line(1), "cd");
+
+    // the original terminators are kept, even across edits spanning lines
+    content.edit(&Range { start: Position { line: 0, character: 1 }, end: Position { line: 1, character: 1 } }, "x\ry");
+    assert_eq!(content.text(), "ax\ryd\r\nef\rgh");
+
+    // a "\n" inserted after a "\r" turns both into a single terminator
+    content.insert(&Position { line: 3, character: 0 }, "\n");
+    assert_eq!(lines(&content), vec!["ax\r", "yd\r\n", "ef\r\n", "gh"]);
+
+    // a document ending with a terminator has an empty last line
+    assert_eq!(lines(&TextDocument::new("ab\n", 0, PositionEncodingKind::Utf16)), vec!["ab\n", ""]);
+}
+
+#[test]
+fn test_lifecycle() {
+    let request = |method: &str| serde_json::from_str::<Message>(&format!("{{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"{method}\"}}")).unwrap();
+    let notification = |method: &str| serde_json::from_str::<Message>(&format!("{{\"jsonrpc\": \"2.0\", \"method\": \"{method}\"}}")).unwrap();
+    let error_code = |admission: Admission| match admission {
+        Admission::Reject(response) => response.error.map(|error| error.code),
+        _ => None
+    };
+
+    assert_eq!(error_code(Lifecycle::Uninitialized.admit(request("textDocument/hover"))), Some(ResponseError::SERVER_NOT_INITIALIZED));
+    assert!(matches!(Lifecycle::Uninitialized.admit(notification("initialized")), Admission::Drop));
+    assert!(matches!(Lifecycle::Uninitialized.admit(notification("exit")), Admission::Route(_)));
+    assert!(matches!(Lifecycle::Uninitialized.admit(request("initialize")), Admission::Route(_)));
+    assert_eq!(error_code(Lifecycle::Initialized.admit(request("initialize"))), Some(ResponseError::INVALID_REQUEST));
+    assert!(matches!(Lifecycle::Initialized.admit(request("shutdown")), Admission::Route(_)));
+    assert_eq!(error_code(Lifecycle::ShutDown.admit(request("textDocument/hover"))), Some(ResponseError::INVALID_REQUEST));
+    assert!(matches!(Lifecycle::ShutDown.admit(notification("exit")), Admission::Route(_)));
+}
+
+#[test]
+fn test_position_encodings() {
+    let range = |start: u32, end: u32| Range {
+        start: Position { line: 0, character: start },
+        end: Position { line: 0, character: end }
+    };
+    let erase = |encoding: PositionEncodingKind, range: Range| {
+        let mut content = TextDocument::new("// ä😀b", 0, encoding);
+        content.erase(&range);
+        content.line(0)
+    };
+
+    // "😀" takes 4 code units in UTF-8, 2 in UTF-16 and 1 in UTF-32
+    assert_eq!(erase(PositionEncodingKind::Utf8, range(5, 9)), "// äb");
+    assert_eq!(erase(PositionEncodingKind::Utf16, range(4, 6)), "// äb");
+    assert_eq!(erase(PositionEncodingKind::Utf32, range(4, 5)), "// äb");
+    assert_eq!(erase(PositionEncodingKind::Utf16, range(5, 42)), "// ä"); // inside the surrogate pair and beyond the line
+
+    let content = TextDocument::new("// ä😀b", 0, PositionEncodingKind::Utf16);
+    assert_eq!(content.position(0, "// ä😀".len()).character, 6);
+
+    // lines beyond the end refer to the end of the text
+    let mut content = TextDocument::new("// ä😀b\nx", 0, PositionEncodingKind::Utf16);
+    assert_eq!(content.offset(&Position { line: 7, character: 0 }), "// ä😀b\nx".len());
+    assert_eq!(content.line(7), "");
+    content.erase(&Range { start: Position { line: 0, character: 3 }, end: Position { line: 7, character: 1 } });
+    assert_eq!(content.line(0), "// ");
+    assert_eq!(content.line_count(), 1);
+
+    assert_eq!(PositionEncodingKind::negotiate(&[]), PositionEncodingKind::Utf16);
+    assert_eq!(PositionEncodingKind::negotiate(&[String::from("utf-16"), String::from("utf-8")]), PositionEncodingKind::Utf8);
+}
+
+#[test]
+fn test_definition_at() {
+    let content = TextDocument::new("fn main() {\n    let 𝑎 = 1; let x = 2;\n    x + y;\n}", 0, PositionEncodingKind::Utf16);
+    let definition = content.definition_at(&Position { line: 2, character: 4 }).unwrap();
+    assert_eq!(definition.kind, resolver::DefinitionKind::Local);
+    assert_eq!(content.offset_position(definition.span.start), Position { line: 1, character: 20 });
+    assert_eq!(content.definition_at(&Position { line: 2, character: 8 }), None); // y is unresolved
+
+    let visible: Vec<String> = content.visible_at(&Position { line: 2, character: 0 }).into_iter().map(|definition| definition.name).collect();
+    assert_eq!(visible, vec!["x", "𝑎", "main"]);
+}
+
+// All requests and notifications get routed to their corresponding handler function
+#[route]
+pub trait Router {
+    fn state(&mut self) -> &mut State;
+
+    #[route("initialize")]
+    fn initialize(&mut self, capabilities: ClientCapabilities, _client_info: Option<ClientInfo>, _locale: Option<String>, initialization_options: Option<InitializationOptions>, root_uri: Option<String>, workspace_folders: Option<Vec<WorkspaceFolder>>) -> Result<InitializeResult, ResponseError> {
+        self.state().lifecycle = Lifecycle::Initialized;
+        if let Some(text_document_sync) = initialization_options.and_then(|options| options.text_document_sync) {
+            self.state().text_document_sync = text_document_sync;
+        }
+        let position_encodings = capabilities.general.as_ref().and_then(|general| general.position_encodings.as_deref());
+        let position_encoding = PositionEncodingKind::negotiate(position_encodings.unwrap_or_default());
+        self.state().position_encoding = position_encoding;
+        self.state().client_capabilities = capabilities;
+
+        // the folders replace the deprecated root, which is the only folder for clients that don't support them
+        let folders = match (workspace_folders, root_uri) {
+            (Some(folders), _) => folders.into_iter().map(|folder| folder.uri).collect(),
+            (None, Some(root_uri)) => vec![root_uri],
+            (None, None) => Vec::new()
+        };
+        for folder in folders {
+            self.state().scan_folder(&folder);
+        }
+
+        Ok(InitializeResult{ 
+            capabilities: ServerCapabilities{
+                position_encoding,
+                text_document_sync: TextDocumentSyncOptions{
+                    open_close: true,
+                    change: self.state().text_document_sync,
+                    will_save: true,
+                    will_save_wait_until: true,
+                    save: SaveOptions { include_text: true }
+                },
+                hover_provider: true,
+                diagnostic_provider: self.state().pulls_diagnostics().then_some(DiagnosticOptions {
+                    inter_file_dependencies: false,
+                    workspace_diagnostics: true
+                }),
+                workspace: WorkspaceServerCapabilities {
+                    workspace_folders: WorkspaceFoldersServerCapabilities {
+                        supported: true,
+                        change_notifications: true
+                    }
+                }
+            },
+            server_info: ServerInfo{ 
+                name: String::from("Descend LSP"), 
+                version: String::from("1.0.0") 
+            } 
+        })
+    }
+
+    #[route("initialized")]
+    fn initialized(&mut self) {
+        self.state().fetch_settings();
+        self.state().register_file_watchers();
+    }
+
+    #[route("workspace/didChangeConfiguration")]
+    fn did_change_configuration(&mut self, _settings: Value) {
+        // the pushed settings are deprecated in favour of pulling them with "workspace/configuration"
+        self.state().fetch_settings();
+    }
+
+    #[route("workspace/didChangeWorkspaceFolders")]
+    fn did_change_workspace_folders(&mut self, event: WorkspaceFoldersChangeEvent) {
+        for folder in event.removed {
+            self.state().workspace.remove_folder(&folder.uri);
+        }
+        for folder in event.added {
+            self.state().scan_folder(&folder.uri);
+        }
+    }
+
+    #[route("workspace/didChangeWatchedFiles")]
+    fn did_change_watched_files(&mut self, changes: Vec<FileEvent>) {
+        let encoding = self.state().position_encoding;
+        for change in changes {
+            match change.typ {
+                FileChangeType::DELETED => self.state().workspace.remove(&change.uri),
+                // created and changed files are (re)read
+                _ => self.state().workspace.refresh(&change.uri, encoding)
+            }
+        }
+        self.state().refresh_diagnostics();
+    }
+
+    #[route("shutdown")]
+    fn shutdown(&mut self) -> Result<(), ResponseError> {
+        self.state().lifecycle = Lifecycle::ShutDown;
+        Ok(())
+    }
+
+    #[route("exit")]
+    fn exit(&mut self) {
+        // the exit code tells the client whether we were shut down properly before
+        let code = match self.state().lifecycle {
+            Lifecycle::ShutDown => 0,
+            _ => 1
+        };
+        self.state().lifecycle = Lifecycle::Exited(code);
+    }
+
+    #[route("$/cancelRequest")]
+    fn cancel_request(&mut self, id: Id) {
+        // requests that are already answered are not pending anymore and can't be cancelled
+        if let Some(token) = self.state().pending_requests.lock().unwrap().get(&id) {
+            token.cancel();
+        }
+    }
+
+    #[route("textDocument/didOpen")]
+    fn did_open_text_document(&mut self, text_document: TextDocumentItem) {
+        self.state().out_of_sync.remove(&text_document.uri); // the document is complete again
+        let encoding = self.state().position_encoding;
+        let text_documents_map= &mut self.state().text_documents;
+        text_documents_map.insert(text_document.uri.clone(), Arc::new(TextDocument::new(&text_document.text, text_document.version, encoding)));
+        self.state().publish_diagnostics(&text_document.uri);
+    }
+
+    #[route("textDocument/didChange")]
+    fn did_change_text_document(&mut self, text_document: VersionedTextDocumentIdentifier, content_changes: Vec<TextDocumentContentChangeEvent>) {
+        let (uri, version) = (text_document.uri, text_document.version);
+        // a change that isn't newer than the document is duplicate or out of order, applying it would corrupt the document
+        if let Some(text_document) = self.state().text_documents.get(&uri) {
+            if version <= text_document.version {
+                eprintln!("Dropping change of \"{uri}\" to version {version}, the document already has version {}", text_document.version);
+                self.state().resync_document(Some(uri));
+                return;
+            }
+        }
+        self.state().modify_document(&uri);
+
+        let encoding = self.state().position_encoding;
+        for content_change in content_changes {
+            let Some(range) = content_change.range else {
+                // the whole text makes the document consistent again
+                self.state().out_of_sync.remove(&uri);
+                self.state().text_documents.insert(uri.clone(), Arc::new(TextDocument::new(&content_change.text, version, encoding)));
+                continue;
+            };
+            if self.state().out_of_sync.contains(&uri) {
+                continue; // changes can't be applied to a document that's already inconsistent
+            }
+            let text_document = self.state().text_documents.get_mut(&uri).unwrap_or_else(|| panic!("Unknown document \"{uri}\""));
+            // copies the document only if a snapshot still refers to the old version
+            let text_document = Arc::make_mut(text_document);
+            text_document.edit(&range, &content_change.text);
+            text_document.version = version;
+        }
+        self.state().publish_diagnostics(&uri);
+    }
+
+    #[route("textDocument/didClose")]
+    fn did_close_text_document(&mut self, text_document: TextDocumentIdentifier) {
+        self.state().out_of_sync.remove(&text_document.uri);
+        let text_documents_map = &mut self.state().text_documents;
+        text_documents_map.remove(&text_document.uri);
+        self.state().clear_diagnostics(&text_document.uri);
+        // the buffer might have been saved or discarded, either way the file on disk is what's left
+        let encoding = self.state().position_encoding;
+        self.state().workspace.refresh(&text_document.uri, encoding);
+    }
+
+    #[route("textDocument/willSave")]
+    fn will_save_text_document(&mut self, _text_document: TextDocumentIdentifier, _reason: u32) {
+        // nothing to prepare, the edits are computed by "willSaveWaitUntil"
+    }
+
+    #[route("textDocument/willSaveWaitUntil")]
+    fn will_save_wait_until(snapshot: &Snapshot, text_document: TextDocumentIdentifier, _reason: u32) -> Result<Vec<TextEdit>, ResponseError> {
+        snapshot.check_in_sync(&text_document.uri)?;
+        // there's no formatter for Descend yet, so the document is saved as it is
+        Ok(Vec::new())
+    }
+
+    #[route("textDocument/didSave")]
+    fn did_save_text_document(&mut self, text_document: TextDocumentIdentifier, text: Option<String>) {
+        let uri = text_document.uri;
+        let encoding = self.state().position_encoding;
+        // the saved text is what the client has, so a document that lost track of it is complete again
+        if let Some(text) = text {
+            if let Some(document) = self.state().text_documents.get(&uri) {
+                let changed = document.text() != text;
+                if changed {
+                    eprintln!("Document \"{uri}\" differs from the saved text, replacing it");
+                    let document = Arc::new(TextDocument::new(&text, document.version, encoding));
+                    self.state().text_documents.insert(uri.clone(), document);
+                    self.state().modify_document(&uri);
+                }
+                self.state().out_of_sync.remove(&uri);
+            }
+        }
+        self.state().workspace.refresh(&uri, encoding);
+        // saving checks the document again, clients that pull diagnostics are asked to pull them again
+        self.state().publish_diagnostics(&uri);
+        self.state().refresh_diagnostics();
+    }
+
+    #[route("textDocument/diagnostic")]
+    fn document_diagnostic(snapshot: &Snapshot, text_document: TextDocumentIdentifier, _identifier: Option<String>, previous_result_id: Option<String>, token: CancellationToken) -> Result<DocumentDiagnosticReport, ResponseError> {
+        token.check()?;
+        snapshot.check_in_sync(&text_document.uri)?;
+        let document = snapshot.document(&text_document.uri).unwrap_or_else(|| panic!("Unknown document \"{}\"", text_document.uri));
+        Ok(diagnostics::report(&text_document.uri, document, snapshot.settings.max_number_of_problems, previous_result_id.as_deref()))
+    }
+
+    #[route("workspace/diagnostic")]
+    fn workspace_diagnostic(snapshot: &Snapshot, _identifier: Option<String>, previous_result_ids: Vec<PreviousResultId>, token: CancellationToken) -> Result<WorkspaceDiagnosticReport, ResponseError> {
+        let previous_result_ids: HashMap<&str, &str> = previous_result_ids.iter().map(|previous| (previous.uri.as_str(), previous.value.as_str())).collect();
+        // the open documents are pulled one by one, this covers the files on disk
+        let mut files: Vec<(&String, &Arc<TextDocument>)> = snapshot.workspace.files.iter().filter(|(uri, _)| !snapshot.text_documents.contains_key(*uri)).collect();
+        files.sort_by_key(|(uri, _)| *uri);
+        let mut items = Vec::new();
+        for (uri, document) in files {
+            token.check()?;
+            let report = diagnostics::report(uri, document, snapshot.settings.max_number_of_problems, previous_result_ids.get(uri.as_str()).copied());
+            items.push(WorkspaceDocumentDiagnosticReport { uri: uri.clone(), version: None, report });
+        }
+        Ok(WorkspaceDiagnosticReport { items })
+    }
+
+    #[route("textDocument/hover")]
+    fn hover(snapshot: &Snapshot, text_document: TextDocumentIdentifier, position: Position, token: CancellationToken) -> Result<Option<Hover>, ResponseError> {
+        token.check()?;
+        snapshot.check_in_sync(&text_document.uri)?;
+        let text_document = snapshot.document(&text_document.uri).unwrap_or_else(|| panic!("Unknown document \"{}\"", text_document.uri));
+        let offset = text_document.offset(&position);
+        let Some((span, description)) = text_document.types().describe_at(&text_document.syntax(), &text_document.resolution(), offset) else {
+            return Ok(None);
+        };
+        Ok(Some(Hover {
+            contents: MarkupContent {
+                kind: String::from("plaintext"),
+                value: description
+            },
+            range: Some(text_document.range(&span))
+        }))
+    }
+}
+
+// Lifecycle phases of the server, see "Server lifetime" in the LSP:
+//
+// Uninitialized --initialize--> Initialized --shutdown--> ShutDown --exit--> Exited(0)
+//
+// An exit notification in any other phase leads to Exited(1).
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Lifecycle {
+    Uninitialized,
+    Initialized,
+    ShutDown,
+    Exited(i32)
+}
+
+// What happens to an incoming message in the current lifecycle phase
+#[derive(Debug)]
+pub enum Admission {
+    Route(Message),
+    Reject(ResponseMessage),
+    Drop
+}
+
+impl Lifecycle {
+    // Decides whether the message may be handled in the current phase
+    fn admit(&self, message: Message) -> Admission {
+        match (self, &message) {
+            (_, Message::Response(_)) => Admission::Route(message),
+            (Lifecycle::Uninitialized, Message::Request(request)) if request.method == "initialize" => Admission::Route(message),
+            (Lifecycle::Uninitialized, Message::Request(_)) => Lifecycle::reject(message, ResponseError::SERVER_NOT_INITIALIZED, "Server is not initialized yet!"),
+            (Lifecycle::Initialized, Message::Request(request)) if request.method == "initialize" => Lifecycle::reject(message, ResponseError::INVALID_REQUEST, "Server is already initialized!"),
+            (Lifecycle::Initialized, _) => Admission::Route(message),
+            (_, Message::Request(_)) => Lifecycle::reject(message, ResponseError::INVALID_REQUEST, "Server is shutting down!"),
+            (_, Message::Notification(notification)) if notification.method == "exit" => Admission::Route(message),
+            (_, Message::Notification(_)) => Admission::Drop // notifications are dropped before initialize and after shutdown
+        }
+    }
+
+    fn reject(message: Message, code: i32, error: &str) -> Admission {
+        match message {
+            Message::Request(request) => Admission::Reject(ResponseMessage::error(request.id, code, String::from(error))),
+            _ => Admission::Drop
+        }
+    }
+}
+
+// Server state
+//
+// The state is owned by the thread handling the events, so notifications like "textDocument/didChange"
+// are applied in order. Read-only requests are handled on the worker pool against a snapshot, and all
+// outgoing messages are serialized by a single writer thread.
+pub struct State {
+    pub lifecycle: Lifecycle,
+    pub client_capabilities: ClientCapabilities,
+    pub position_encoding: PositionEncodingKind, // negotiated during "initialize"
+    pub text_document_sync: TextDocumentSyncKind, // requested by the client during "initialize", incremental by default
+    pub settings: Settings,
+    pub pending_requests: Arc<Mutex<HashMap<Id, CancellationToken>>>,
+    pub outgoing_requests: HashMap<Id, ResponseCallback>,
+    pub next_request_id: u64,
+    pub text_documents: HashMap<String, Arc<TextDocument>>,
+    pub out_of_sync: HashSet<String>, // documents that need to be reopened, as a handler panicked while changing them
+    pub workspace: Workspace, // files on disk, overlaid by the open text documents
+    pub diagnostics_generation: u64,
+    pub latest_diagnostics: Arc<Mutex<HashMap<String, u64>>>, // generation of the latest diagnostics of each open document
+    pub outgoing: Sender<Message>,
+    pub events: Sender<Event>, // for tasks that hand their results back to the state
+    pub workers: WorkerPool
+}
+
+// Called with the result of a request sent to the client, once the response arrives
+pub type ResponseCallback = Box<dyn FnOnce(&mut State, Result<Value, ResponseError>)>;
+
+// Finishes work of the worker pool on the thread owning the state, e.g. stores the files of a scanned folder
+pub type Task = Box<dyn FnOnce(&mut State) + Send>;
+
+// What the server handles, one after another in the order they arrive
+pub enum Event {
+    Message(Message),
+    Task(Task),
+    Closed // the client closed the stream of messages
+}
+
+// Immutable view of the server state at the time a request was received
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub text_documents: HashMap<String, Arc<TextDocument>>,
+    pub out_of_sync: HashSet<String>,
+    pub workspace: Workspace,
+    pub settings: Settings
+}
+
+impl Snapshot {
+    // The open document or, if it isn't open, the file on disk
+    pub fn document(&self, uri: &str) -> Option<&Arc<TextDocument>> {
+        self.text_documents.get(uri).or_else(|| self.workspace.files.get(uri))
+    }
+
+    // All documents of the workspace and the open ones outside of it
+    pub fn documents(&self) -> impl Iterator<Item = (&String, &Arc<TextDocument>)> {
+        let on_disk = self.workspace.files.iter().filter(|(uri, _)| !self.text_documents.contains_key(*uri));
+        self.text_documents.iter().chain(on_disk)
+    }
+
+    // Results for documents that are out of sync would refer to content the client doesn't have
+    pub fn check_in_sync(&self, uri: &str) -> Result<(), ResponseError> {
+        if self.out_of_sync.contains(uri) {
+            Err(ResponseError { code: ResponseError::CONTENT_MODIFIED, message: format!("Document \"{uri}\" is out of sync!"), data: None })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl State {
+    pub fn new(outgoing: Sender<Message>, events: Sender<Event>, workers: WorkerPool) -> State {
+        State {
+            lifecycle: Lifecycle::Uninitialized,
+            client_capabilities: ClientCapabilities::default(),
+            position_encoding: PositionEncodingKind::default(),
+            text_document_sync: TextDocumentSyncKind::default(),
+            settings: Settings::default(),
+            pending_requests: Arc::new(Mutex::new(HashMap::new())),
+            outgoing_requests: HashMap::new(),
+            next_request_id: 0,
+            text_documents: HashMap::new(),
+            out_of_sync: HashSet::new(),
+            workspace: Workspace::default(),
+            diagnostics_generation: 0,
+            latest_diagnostics: Arc::new(Mutex::new(HashMap::new())),
+            outgoing,
+            events,
+            workers
+        }
+    }
+
+    // Registers an incoming request as pending, so it can be cancelled until it's answered
+    pub fn begin_request(&mut self, id: &Id, document: Option<String>) -> CancellationToken {
+        let mut pending_requests = self.pending_requests.lock().unwrap();
+        let token = pending_requests.entry(id.clone()).or_default();
+        token.document = document;
+        token.clone()
+    }
+
+    // Cancels the pending requests working on a document that changed, with CONTENT_MODIFIED
+    pub fn modify_document(&mut self, uri: &str) {
+        for token in self.pending_requests.lock().unwrap().values() {
+            if token.document.as_deref() == Some(uri) {
+                token.modify();
+            }
+        }
+    }
+
+    pub fn end_request(&mut self, id: &Id) {
+        self.pending_requests.lock().unwrap().remove(id);
+    }
+
+    // Sends a request to the client. Its response is routed back to the callback, which gets the
+    // result parsed as R or the error the client responded with.
+    pub fn send_request<P: Serialize, R: DeserializeOwned>(&mut self, method: &str, params: P, callback: impl FnOnce(&mut State, Result<R, ResponseError>) + 'static) {
+        let id = Id::AsInt(self.next_request_id);
+        self.next_request_id += 1;
+
+        let params = match serde_json::to_value(params) {
+            Ok(params) => params,
+            Err(error) => {
+                eprintln!("Error while serializing params of {method}: {error}");
+                return;
+            }
+        };
+
+        self.outgoing_requests.insert(id.clone(), Box::new(move |state, result| {
+            let result = result.and_then(|result| serde_json::from_value::<R>(result).map_err(|error| ResponseError {
+                code: ResponseError::PARSE_ERROR,
+                message: error.to_string(),
+                data: None
+            }));
+            callback(state, result);
+        }));
+        self.outgoing.send(Message::Request(RequestMessage {
+            jsonrpc: String::from("2.0"),
+            id,
+            method: String::from(method),
+            params
+        })).unwrap_or(());
+    }
+
+    // Delivers the response of the client to the callback of the corresponding request
+    pub fn handle_response(&mut self, response: ResponseMessage) {
+        let callback = match self.outgoing_requests.remove(&response.id) {
+            Some(callback) => callback,
+            None => {
+                eprintln!("Received response to unknown request {:?}!", response.id);
+                return;
+            }
+        };
+
+        let result = match response.error {
+            Some(error) => Err(error),
+            None => Ok(response.result.unwrap_or(Value::Null)) // a null result is deserialized as None
+        };
+        callback(self, result);
+    }
+
+    // Pulls the settings of the "DescendServer" section, if the client supports it
+    pub fn fetch_settings(&mut self) {
+        let supported = self.client_capabilities.workspace.as_ref().and_then(|workspace| workspace.configuration).unwrap_or(false);
+        if !supported {
+            return;
+        }
+
+        let params = ConfigurationParams {
+            items: vec![ConfigurationItem { scope_uri: None, section: Some(String::from("DescendServer")) }]
+        };
+        self.send_request("workspace/configuration", params, |state, result: Result<Vec<Option<Settings>>, ResponseError>| {
+            match result {
+                Ok(mut settings) => {
+                    state.settings = settings.pop().flatten().unwrap_or_default();
+                    // the number of problems to report may have changed
+                    state.refresh_diagnostics();
+                    let uris: Vec<String> = state.text_documents.keys().cloned().collect();
+                    for uri in uris {
+                        state.publish_diagnostics(&uri);
+                    }
+                },
+                Err(error) => eprintln!("Error while fetching settings: {}", error.message)
+            }
+        });
+    }
+
+    // Asks the client to notify us about changes of Descend files on disk, e.g. by a checkout or a code generator
+    pub fn register_file_watchers(&mut self) {
+        let supported = self.client_capabilities.workspace.as_ref()
+            .and_then(|workspace| workspace.did_change_watched_files.as_ref())
+            .and_then(|did_change_watched_files| did_change_watched_files.dynamic_registration)
+            .unwrap_or(false);
+        if !supported {
+            return;
+        }
+
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher { glob_pattern: format!("**/*.{}", workspace::EXTENSION) }]
+        };
+        let params = RegistrationParams {
+            registrations: vec![Registration {
+                id: String::from("descend-file-watcher"),
+                method: String::from("workspace/didChangeWatchedFiles"),
+                register_options: serde_json::to_value(options).ok()
+            }]
+        };
+        self.send_request("client/registerCapability", params, |_, result: Result<Value, ResponseError>| {
+            if let Err(error) = result {
+                eprintln!("Error while registering file watchers: {}", error.message);
+            }
+        });
+    }
+
+    // Scans a new workspace folder on the worker pool, its files are added once they are read. Clients
+    // that pull diagnostics are asked to pull them again for the new files.
+    pub fn scan_folder(&mut self, uri: &str) {
+        if !self.workspace.add_folder(uri) {
+            return;
+        }
+        let uri = uri.to_string();
+        let encoding = self.position_encoding;
+        let events = self.events.clone();
+        self.workers.execute(move || {
+            let files = workspace::scan(&uri, encoding);
+            events.send(Event::Task(Box::new(move |state| {
+                state.workspace.finish_scan(&uri, files);
+                state.refresh_diagnostics();
+            }))).unwrap_or(());
+        });
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            text_documents: self.text_documents.clone(), // only clones the pointers to the documents
+            out_of_sync: self.out_of_sync.clone(),
+            workspace: self.workspace.clone(), // shares the files as well
+            settings: self.settings.clone()
+        }
+    }
+
+    // Marks a document as out of sync after a handler panicked while working on it or its changes arrived out of order.
+    // The LSP has no way for the server to ask for the content again, so the user is asked to reopen it.
+    pub fn resync_document(&mut self, uri: Option<String>) {
+        let uri = match uri {
+            Some(uri) if self.text_documents.contains_key(&uri) => uri,
+            _ => return
+        };
+        if self.out_of_sync.insert(uri.clone()) {
+            self.clear_diagnostics(&uri);
+            self.send_notification("window/showMessage", ShowMessageParams {
+                typ: MessageType::ERROR,
+                message: format!("The Descend language server lost track of \"{uri}\", please close and reopen it.")
+            });
+        }
+    }
+
+    pub fn send_notification<P: Serialize>(&mut self, method: &str, params: P) {
+        send_notification(&self.outgoing, method, params);
+    }
+
+    // Clients that support it pull the diagnostics with "textDocument/diagnostic" instead of getting them pushed
+    pub fn pulls_diagnostics(&self) -> bool {
+        self.client_capabilities.text_document.as_ref().is_some_and(|text_document| text_document.diagnostic.is_some())
+    }
+
+    // Asks a client that pulls diagnostics to pull them again, as they changed without a change of the open documents
+    pub fn refresh_diagnostics(&mut self) {
+        let supported = self.client_capabilities.workspace.as_ref()
+            .and_then(|workspace| workspace.diagnostics.as_ref())
+            .and_then(|diagnostics| diagnostics.refresh_support)
+            .unwrap_or(false);
+        if !supported || !self.pulls_diagnostics() {
+            return;
+        }
+        self.send_request("workspace/diagnostic/refresh", Value::Null, |_, result: Result<Value, ResponseError>| {
+            if let Err(error) = result {
+                eprintln!("Error while refreshing diagnostics: {}", error.message);
+            }
+        });
+    }
+
+    // Checks the document on the worker pool and pushes its diagnostics. The workers may finish out of
+    // order, so only the diagnostics of the latest generation for the document are sent.
+    pub fn publish_diagnostics(&mut self, uri: &str) {
+        if self.pulls_diagnostics() || self.out_of_sync.contains(uri) {
+            return; // out of sync, the ranges would refer to content the client doesn't have
+        }
+        let Some(document) = self.text_documents.get(uri).cloned() else {
+            return;
+        };
+        self.diagnostics_generation += 1;
+        let generation = self.diagnostics_generation;
+        self.latest_diagnostics.lock().unwrap().insert(uri.to_string(), generation);
+
+        let uri = uri.to_string();
+        let max_number_of_problems = self.settings.max_number_of_problems;
+        let latest_diagnostics = self.latest_diagnostics.clone();
+        let outgoing = self.outgoing.clone();
+        self.workers.execute(move || {
+            let diagnostics = diagnostics::diagnostics(&uri, &document, max_number_of_problems);
+            // the lock is held while sending, so a newer generation or clearing can't overtake us
+            let latest_diagnostics = latest_diagnostics.lock().unwrap();
+            if latest_diagnostics.get(&uri) == Some(&generation) {
+                send_notification(&outgoing, "textDocument/publishDiagnostics", PublishDiagnosticsParams { uri, version: Some(document.version), diagnostics });
+            }
+        });
+    }
+
+    // Removes the diagnostics of a document that was closed, jobs still working on it won't send theirs
+    pub fn clear_diagnostics(&mut self, uri: &str) {
+        let mut latest_diagnostics = self.latest_diagnostics.lock().unwrap();
+        if latest_diagnostics.remove(uri).is_some() {
+            send_notification(&self.outgoing, "textDocument/publishDiagnostics", PublishDiagnosticsParams { uri: uri.to_string(), version: None, diagnostics: Vec::new() });
+        }
+    }
+
+    // Answers a request on the worker pool, the job gets a snapshot of the current state
+    pub fn spawn(&mut self, job: impl FnOnce(&Snapshot) -> ResponseMessage + Send + 'static) {
+        let snapshot = self.snapshot();
+        let pending_requests = self.pending_requests.clone();
+        let outgoing = self.outgoing.clone();
+
+        self.workers.execute(move || {
+            let response = job(&snapshot);
+            pending_requests.lock().unwrap().remove(&response.id);
+            outgoing.send(Message::Response(response)).unwrap_or(());
+        });
+    }
+}
+
+impl Router for State {
+    fn state(&mut self) -> &mut State {
+        self
+    }
+}
+
+fn send_notification<P: Serialize>(outgoing: &Sender<Message>, method: &str, params: P) {
+    let params = match serde_json::to_value(params) {
+        Ok(params) => params,
+        Err(error) => {
+            eprintln!("Error while serializing params of {method}: {error}");
+            return;
+        }
+    };
+    outgoing.send(Message::Notification(NotificationMessage {
+        jsonrpc: String::from("2.0"),
+        method: String::from(method),
+        params
+    })).unwrap_or(());
+}
+
+// Maps an error while reading a message to the response for the client
+fn get_response(error: FramingError) -> Option<ResponseMessage> {
+    let message = error.to_string();
+    let (id, code) = match error {
+        FramingError::Eof | FramingError::Io(_) | FramingError::TruncatedContent { .. } => return None,
+        FramingError::MalformedHeader(_) | FramingError::InvalidContentLength(_) | FramingError::MissingContentLength => (None, ResponseError::PARSE_ERROR),
+        FramingError::UnsupportedCharset(_) | FramingError::InvalidUtf8(_) | FramingError::InvalidJson(_) => (None, ResponseError::PARSE_ERROR),
+        FramingError::InvalidMessage { id, .. } => (id, ResponseError::INVALID_REQUEST)
+    };
+    // the id is null if it couldn't be determined
+    Some(ResponseMessage::error(id.unwrap_or(Id::AsJson(Value::Null)), code, message))
+}
+
+// Handles a single incoming message, returns the response if it's answered right away
+pub fn dispatch(server: &mut (impl Router + 'static), message: Message) -> Option<ResponseMessage> {
+    match server.state().lifecycle.admit(message) {
+        Admission::Route(message) => route_msg(server, message), // "route_msg" generated by the router macro
+        Admission::Reject(response) => Some(response),
+        Admission::Drop => None
+    }
+}
+
+#[test]
+fn test_outgoing_requests() {
+    let (outgoing, messages) = channel::<Message>();
+    let mut state = State::new(outgoing, channel().0, WorkerPool::new(1));
+
+    state.send_request("workspace/configuration", Value::Null, |state, result: Result<Vec<Option<Settings>>, ResponseError>| {
+        state.settings = result.unwrap().pop().flatten().unwrap();
+    });
+    let id = match messages.try_recv() {
+        Ok(Message::Request(request)) => request.id,
+        message => panic!("Expected request, got {:?}", message)
+    };
+
+    // responses to unknown requests are ignored
+    state.handle_response(ResponseMessage { jsonrpc: String::from("2.0"), id: Id::AsInt(42), result: None, error: None });
+    assert_eq!(state.outgoing_requests.len(), 1);
+
+    let result = serde_json::json!([{ "maxNumberOfProblems": 5 }]);
+    state.handle_response(ResponseMessage { jsonrpc: String::from("2.0"), id, result: Some(result), error: None });
+    assert_eq!(state.settings.max_number_of_problems, 5);
+    assert!(state.outgoing_requests.is_empty());
+}
+
+// Writes all outgoing messages in the order they are sent through the channel. The thread finishes
+// once all senders are dropped and the messages queued until then are written.
+fn spawn_writer(mut buf: impl Write + Send + 'static) -> (Sender<Message>, JoinHandle<()>) {
+    let (outgoing, messages) = channel::<Message>();
+
+    let writer = std::thread::spawn(move || {
+        for message in messages {
+            let message = match serde_json::to_value(message) {
+                Ok(message) => message,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    continue;
+                }
+            };
+
+            let message = RawMessage::from(message);
+            message.write(&mut buf).unwrap_or(());
+            buf.flush().unwrap_or(());
+        }
+    });
+
+    (outgoing, writer)
+}
+
+// Runs the server until the client sends "exit" and returns the exit code.
+// The messages are exchanged over channels, which allows embedding the server and driving it in-process.
+// They are received on a thread of their own and queued with the tasks finished by the worker pool.
+pub fn serve(incoming: impl IntoIterator<Item = Message, IntoIter: Send + 'static>, outgoing: Sender<Message>) -> i32 {
+    let (events, queue) = channel::<Event>();
+    let messages = events.clone();
+    let incoming = incoming.into_iter();
+    std::thread::spawn(move || {
+        for message in incoming {
+            if messages.send(Event::Message(message)).is_err() {
+                return; // the server is gone
+            }
+        }
+        messages.send(Event::Closed).unwrap_or(());
+    });
+
+    let mut server = State::new(outgoing.clone(), events, WorkerPool::with_available_parallelism());
+    for event in queue {
+        match event {
+            Event::Message(message) => {
+                if let Some(response) = dispatch(&mut server, message) {
+                    outgoing.send(Message::Response(response)).unwrap_or(());
+                }
+            },
+            Event::Task(task) => task(&mut server),
+            Event::Closed => break
+        }
+
+        if let Lifecycle::Exited(code) = server.lifecycle {
+            return code;
+        }
+    }
+
+    // the client is gone without sending "exit", which is fine if it sent "shutdown" before
+    match server.lifecycle {
+        Lifecycle::ShutDown => 0,
+        _ => 1
+    }
+}
+
+// Runs the server on the byte streams of a transport, see serve. Returns once all messages are written.
+pub fn run(input: impl BufRead + Send + 'static, output: impl Write + Send + 'static) -> i32 {
+    let (outgoing, writer) = spawn_writer(output);
+    // the reading thread may still be blocked on the input when the server exits, so it can't keep
+    // the writer alive with a sender of its own
+    let errors = Arc::new(Mutex::new(Some(outgoing.clone())));
+    let reader_errors = errors.clone();
+    let mut reader = MessageReader::new(input);
+
+    // the messages end with the stream, the server then shuts down as if it received "exit"
+    let incoming = std::iter::from_fn(move || loop {
+        let message = reader.read().and_then(|message| Message::from_raw(&message));
+        match message {
+            Ok(message) => return Some(message),
+            Err(error) => {
+                if !matches!(error, FramingError::Eof) {
+                    eprintln!("{error}");
+                }
+                if error.is_fatal() {
+                    return None;
+                }
+                if let (Some(response), Some(errors)) = (get_response(error), &*reader_errors.lock().unwrap()) {
+                    errors.send(Message::Response(response)).unwrap_or(());
+                }
+            }
+        }
+    });
+
+    let code = serve(incoming, outgoing);
+    // the server has dropped its senders, the writer finishes once this one is gone as well
+    errors.lock().unwrap().take();
+    writer.join().unwrap_or(());
+    code
+}
+
+#[test]
+fn test_run() {
+    let (input, mut client) = std::io::pipe().unwrap();
+    let (mut written, output) = std::io::pipe().unwrap();
+    let requests = [
+        "{", // answered by the reading thread
+        r#"{"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {"capabilities": {}}}"#,
+        r#"{"jsonrpc": "2.0", "method": "initialized", "params": {}}"#,
+        r#"{"jsonrpc": "2.0", "id": 2, "method": "shutdown"}"#,
+        r#"{"jsonrpc": "2.0", "method": "exit"}"#
+    ];
+    for content in requests {
+        write!(client, "Content-Length: {}\r\n\r\n{content}", content.len()).unwrap();
+    }
+
+    // the client keeps the input open, the server returns after exit anyway, once all responses are written
+    assert_eq!(run(std::io::BufReader::new(input), output), 0);
+    let mut responses = String::new();
+    written.read_to_string(&mut responses).unwrap();
+    let ids: Vec<Value> = responses.split("Content-Length").skip(1).map(|frame| {
+        let content = &frame[frame.find("\r\n\r\n").unwrap() + 4..];
+        serde_json::from_str::<Value>(content).unwrap()["id"].clone()
+    }).collect();
+    assert_eq!(ids, vec![Value::Null, Value::from(1), Value::from(2)]);
+    drop(client);
+}
@@ -0,0 +1,1076 @@
+use crate::{lexer::{lex, Token, TokenKind}, syntax::{Builder, Checkpoint, Depth, SyntaxError, SyntaxKind, SyntaxTree}};
+
+// Tokens that start the next item, where the parser resynchronizes after errors
+const ITEM_START: &[TokenKind] = &[TokenKind::Fn, TokenKind::Struct];
+
+const MEMORY: &[TokenKind] = &[TokenKind::CpuMem, TokenKind::GpuGlobal, TokenKind::GpuShared, TokenKind::GpuLocal];
+
+const EXEC: &[TokenKind] = &[TokenKind::CpuThread, TokenKind::GpuGrid, TokenKind::GpuBlock, TokenKind::GpuWarp, TokenKind::GpuThread];
+
+const KINDS: &[TokenKind] = &[TokenKind::Nat, TokenKind::Mem, TokenKind::Ty, TokenKind::Dty, TokenKind::Prv];
+
+const LITERALS: &[TokenKind] = &[TokenKind::Integer, TokenKind::Float, TokenKind::Char, TokenKind::String, TokenKind::True, TokenKind::False];
+
+// Binding power of binary operators, assignments are right associative
+fn infix_binding_power(kind: TokenKind) -> Option<(u8, u8)> {
+    Some(match kind {
+        TokenKind::Eq | TokenKind::PlusEq | TokenKind::MinusEq | TokenKind::StarEq | TokenKind::SlashEq => (2, 1),
+        TokenKind::DotDot => (3, 4),
+        TokenKind::PipePipe => (5, 6),
+        TokenKind::AmpAmp => (7, 8),
+        TokenKind::EqEq | TokenKind::Ne | TokenKind::Lt | TokenKind::Le | TokenKind::Gt | TokenKind::Ge => (9, 10),
+        TokenKind::Plus | TokenKind::Minus | TokenKind::Pipe | TokenKind::Caret => (11, 12),
+        TokenKind::Star | TokenKind::Slash | TokenKind::Percent | TokenKind::Amp => (13, 14),
+        _ => return None
+    })
+}
+
+const PREFIX_BINDING_POWER: u8 = 15;
+
+// Nat expressions in generic arguments end at ">" and ",", so they can't contain comparisons
+const NAT_BINDING_POWER: u8 = 11;
+
+struct Parser<'t> {
+    text: &'t str,
+    tokens: Vec<Token>,
+    position: usize, // index of the next token, including trivia
+    builder: Builder,
+    errors: Vec<SyntaxError>,
+    delimiters: Vec<(TokenKind, std::ops::Range<usize>)>, // opening delimiters that aren't closed yet
+    depth: Depth // of the expressions, types and blocks being parsed
+}
+
+fn opening_delimiter(close: TokenKind) -> Option<TokenKind> {
+    match close {
+        TokenKind::RParen => Some(TokenKind::LParen),
+        TokenKind::RBracket => Some(TokenKind::LBracket),
+        TokenKind::RBrace => Some(TokenKind::LBrace),
+        _ => None
+    }
+}
+
+impl<'t> Parser<'t> {
+    // Kind of the n-th token ahead, skipping trivia
+    fn nth(&self, n: usize) -> Option<TokenKind> {
+        self.tokens[self.position..].iter().filter(|token| !token.kind.is_trivia()).nth(n).map(|token| token.kind)
+    }
+
+    fn current(&self) -> Option<TokenKind> {
+        self.nth(0)
+    }
+
+    fn at(&self, kind: TokenKind) -> bool {
+        self.current() == Some(kind)
+    }
+
+    fn at_any(&self, kinds: &[TokenKind]) -> bool {
+        self.current().is_some_and(|kind| kinds.contains(&kind))
+    }
+
+    fn at_end(&self) -> bool {
+        self.current().is_none()
+    }
+
+    // Adds the trivia before the next token to the current node
+    fn skip_trivia(&mut self) {
+        while let Some(token) = self.tokens.get(self.position) {
+            if !token.kind.is_trivia() {
+                break;
+            }
+            self.builder.token(token.clone());
+            self.position += 1;
+        }
+    }
+
+    fn bump(&mut self) {
+        self.skip_trivia();
+        if let Some(token) = self.tokens.get(self.position).cloned() {
+            match token.kind {
+                TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => self.delimiters.push((token.kind, token.span.clone())),
+                kind => {
+                    self.close_delimiter(kind);
+                }
+            }
+            self.builder.token(token);
+            self.position += 1;
+        }
+    }
+
+    // Forgets the innermost opening delimiter matching the closing one, and the ones left open inside of it
+    fn close_delimiter(&mut self, close: TokenKind) -> Option<std::ops::Range<usize>> {
+        let open = opening_delimiter(close)?;
+        let index = self.delimiters.iter().rposition(|(kind, _)| *kind == open)?;
+        self.delimiters.drain(index..).next().map(|(_, span)| span)
+    }
+
+    fn eat(&mut self, kind: TokenKind) -> bool {
+        if self.at(kind) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn start_node(&mut self, kind: SyntaxKind) {
+        self.skip_trivia();
+        self.builder.start_node(kind);
+    }
+
+    fn start_node_at(&mut self, checkpoint: Checkpoint, kind: SyntaxKind) {
+        self.builder.start_node_at(checkpoint, kind);
+    }
+
+    fn finish_node(&mut self) {
+        self.builder.finish_node();
+    }
+
+    fn checkpoint(&mut self) -> Checkpoint {
+        self.skip_trivia();
+        self.builder.checkpoint()
+    }
+
+    // Span of the next token, or an empty one at the end of the text
+    fn current_span(&self) -> std::ops::Range<usize> {
+        self.tokens[self.position..].iter().find(|token| !token.kind.is_trivia())
+            .map_or(self.text.len()..self.text.len(), |token| token.span.clone())
+    }
+
+    fn error(&mut self, message: impl Into<String>) {
+        if self.at(TokenKind::Error) {
+            return; // the lexer already reported the invalid token
+        }
+        let span = self.current_span();
+        self.errors.push(SyntaxError { span, message: message.into(), code: "syntax-error", related: None });
+    }
+
+    fn expect(&mut self, kind: TokenKind, description: &str) -> bool {
+        if self.eat(kind) {
+            return true;
+        }
+        self.error(format!("Expected {description}"));
+        // a missing closing delimiter points at the opening one, which is usually where the mistake is
+        if let Some(open) = self.close_delimiter(kind) {
+            if let Some(error) = self.errors.last_mut() {
+                error.related = Some((open, String::from("Unclosed delimiter")));
+            }
+        }
+        false
+    }
+
+    // Reports the error and skips the next token, unless it's one the caller can continue with
+    fn error_and_bump(&mut self, message: &str, recovery: &[TokenKind]) {
+        self.error(message);
+        if self.at_end() || self.at_any(recovery) || self.at_any(ITEM_START) || self.at(TokenKind::RBrace) {
+            return;
+        }
+        self.start_node(SyntaxKind::Error);
+        self.bump();
+        self.finish_node();
+    }
+
+    // Goes one level deeper into nested code. Code that's nested too deeply is skipped as a single
+    // error instead, up to the end of the enclosing expression.
+    fn enter(&mut self) -> bool {
+        if self.depth.enter() {
+            return true;
+        }
+        self.error("Code is nested too deeply");
+        self.start_node(SyntaxKind::Error);
+        let mut open = 0usize;
+        while let Some(kind) = self.current() {
+            match kind {
+                TokenKind::LParen | TokenKind::LBracket | TokenKind::LBrace => open += 1,
+                TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace if open == 0 => break,
+                TokenKind::RParen | TokenKind::RBracket | TokenKind::RBrace => open -= 1,
+                TokenKind::Semicolon | TokenKind::Comma if open == 0 => break,
+                kind if ITEM_START.contains(&kind) => break,
+                _ => {}
+            }
+            self.bump();
+        }
+        self.finish_node();
+        false
+    }
+
+    fn exit(&mut self) {
+        self.depth.exit();
+    }
+
+    fn name(&mut self, description: &str) {
+        if self.at(TokenKind::Ident) {
+            self.start_node(SyntaxKind::Name);
+            self.bump();
+            self.finish_node();
+        } else {
+            self.error(format!("Expected {description}"));
+        }
+    }
+
+    fn name_ref(&mut self) {
+        self.start_node(SyntaxKind::NameRef);
+        self.bump();
+        self.finish_node();
+    }
+
+    // Comma separated list until the closing token, the elements parse themselves
+    fn list(&mut self, close: TokenKind, description: &str, mut element: impl FnMut(&mut Self)) {
+        while !self.at(close) && !self.at_end() && !self.at_any(ITEM_START) {
+            let position = self.position;
+            element(self);
+            if self.at(close) || self.eat(TokenKind::Comma) {
+                continue;
+            }
+            // a missing comma is reported once, the list continues if the element made progress
+            if self.position == position {
+                self.error_and_bump(&format!("Unexpected token in {description}"), &[close, TokenKind::LBrace, TokenKind::Semicolon]);
+            } else {
+                self.error(format!("Expected ',' between {description}"));
+            }
+            if self.position == position || self.at_any(&[TokenKind::LBrace, TokenKind::RBrace, TokenKind::Semicolon]) {
+                return;
+            }
+        }
+    }
+
+    // Items
+
+    fn source_file(&mut self) {
+        while !self.at_end() {
+            match self.current() {
+                Some(TokenKind::Fn) => self.fn_decl(),
+                Some(TokenKind::Struct) => self.struct_decl(),
+                _ => {
+                    // everything up to the next item is a single error
+                    self.error("Expected a function or struct declaration");
+                    self.start_node(SyntaxKind::Error);
+                    while !self.at_end() && !self.at_any(ITEM_START) {
+                        self.bump();
+                    }
+                    self.finish_node();
+                }
+            }
+        }
+        self.skip_trivia();
+    }
+
+    // fn name<n: nat>(param: type) -[t: gpu.thread]-> type { ... }
+    fn fn_decl(&mut self) {
+        self.start_node(SyntaxKind::FnDecl);
+        self.bump();
+        self.name("function name");
+        if self.at(TokenKind::Lt) {
+            self.generic_param_list();
+        }
+        if self.at(TokenKind::LParen) {
+            self.param_list();
+        } else {
+            self.error("Expected parameter list");
+        }
+        if self.at(TokenKind::Minus) && self.nth(1) == Some(TokenKind::LBracket) {
+            self.exec_annotation();
+            self.ret_type();
+        } else if self.eat(TokenKind::Arrow) {
+            self.ret_type();
+        }
+        if self.at(TokenKind::LBrace) {
+            self.block();
+        } else {
+            self.error("Expected function body");
+        }
+        self.finish_node();
+    }
+
+    fn generic_param_list(&mut self) {
+        self.start_node(SyntaxKind::GenericParamList);
+        self.bump();
+        self.list(TokenKind::Gt, "generic parameters", |parser| {
+            parser.start_node(SyntaxKind::GenericParam);
+            parser.name("generic parameter name");
+            if parser.expect(TokenKind::Colon, "':' after generic parameter") && !parser.at_any(KINDS) {
+                parser.error("Expected kind nat, mem, ty, dty or prv");
+            }
+            if parser.at_any(KINDS) {
+                parser.bump();
+            }
+            parser.finish_node();
+        });
+        self.expect(TokenKind::Gt, "'>'");
+        self.finish_node();
+    }
+
+    fn param_list(&mut self) {
+        self.start_node(SyntaxKind::ParamList);
+        self.bump();
+        self.list(TokenKind::RParen, "parameters", |parser| {
+            parser.start_node(SyntaxKind::Param);
+            parser.eat(TokenKind::Mut);
+            parser.name("parameter name");
+            if parser.expect(TokenKind::Colon, "':' after parameter name") {
+                parser.ty();
+            }
+            parser.finish_node();
+        });
+        self.expect(TokenKind::RParen, "')'");
+        self.finish_node();
+    }
+
+    // -[t: gpu.thread]->
+    fn exec_annotation(&mut self) {
+        self.start_node(SyntaxKind::ExecAnnotation);
+        self.bump();
+        self.bump();
+        self.name("execution resource name");
+        if self.expect(TokenKind::Colon, "':' after execution resource name") {
+            self.exec_type();
+        }
+        self.expect(TokenKind::RBracket, "']'");
+        self.expect(TokenKind::Arrow, "'->'");
+        self.finish_node();
+    }
+
+    fn exec_type(&mut self) {
+        self.start_node(SyntaxKind::ExecType);
+        if self.at_any(EXEC) {
+            self.bump();
+        } else if self.at(TokenKind::Ident) {
+            self.name_ref();
+        } else {
+            self.error("Expected execution resource like gpu.grid or cpu.thread");
+        }
+        if self.at(TokenKind::Lt) {
+            self.generic_arg_list();
+        }
+        self.finish_node();
+    }
+
+    fn ret_type(&mut self) {
+        self.start_node(SyntaxKind::RetType);
+        self.ty();
+        self.finish_node();
+    }
+
+    // struct Name<n: nat> { field: type, ... }
+    fn struct_decl(&mut self) {
+        self.start_node(SyntaxKind::StructDecl);
+        self.bump();
+        self.name("struct name");
+        if self.at(TokenKind::Lt) {
+            self.generic_param_list();
+        }
+        if self.expect(TokenKind::LBrace, "'{'") {
+            self.list(TokenKind::RBrace, "fields", |parser| {
+                parser.start_node(SyntaxKind::StructField);
+                parser.name("field name");
+                if parser.expect(TokenKind::Colon, "':' after field name") {
+                    parser.ty();
+                }
+                parser.finish_node();
+            });
+            self.expect(TokenKind::RBrace, "'}'");
+        }
+        self.finish_node();
+    }
+
+    // Types
+
+    fn ty(&mut self) {
+        if !self.enter() {
+            return;
+        }
+        let checkpoint = self.checkpoint();
+        match self.current() {
+            Some(TokenKind::Amp) => self.ref_type(),
+            Some(TokenKind::LBracket) => self.array_type(),
+            Some(TokenKind::LParen) => self.tuple_type(),
+            Some(TokenKind::Ident) => {
+                self.start_node(SyntaxKind::PathType);
+                self.name_ref();
+                if self.at(TokenKind::Lt) {
+                    self.generic_arg_list();
+                }
+                self.finish_node();
+            },
+            _ => {
+                self.error_and_bump("Expected type", &[TokenKind::Comma, TokenKind::RParen, TokenKind::Gt, TokenKind::Semicolon, TokenKind::Eq, TokenKind::LBrace]);
+                self.exit();
+                return;
+            }
+        }
+        // T @ gpu.shared
+        if self.at(TokenKind::At) {
+            self.start_node_at(checkpoint, SyntaxKind::AtType);
+            self.bump();
+            self.memory();
+            self.finish_node();
+        }
+        self.exit();
+    }
+
+    // &'r uniq gpu.global [f64; n]
+    fn ref_type(&mut self) {
+        self.start_node(SyntaxKind::RefType);
+        self.bump();
+        // the provenance is a lifetime or a generic parameter of kind prv
+        if self.at(TokenKind::Lifetime) {
+            self.bump();
+        } else if self.at(TokenKind::Ident) && matches!(self.nth(1), Some(TokenKind::Uniq | TokenKind::Shrd)) {
+            self.name_ref();
+        }
+        if !self.eat(TokenKind::Uniq) && !self.eat(TokenKind::Shrd) {
+            self.error("Expected 'uniq' or 'shrd'");
+        }
+        self.memory();
+        self.ty();
+        self.finish_node();
+    }
+
+    fn memory(&mut self) {
+        if self.at_any(MEMORY) {
+            self.bump();
+        } else if self.at(TokenKind::Ident) {
+            self.name_ref();
+        } else {
+            self.error("Expected memory like gpu.global or cpu.mem");
+        }
+    }
+
+    // [T; n] or the view [[T; n]]
+    fn array_type(&mut self) {
+        let view = self.nth(1) == Some(TokenKind::LBracket);
+        self.start_node(if view { SyntaxKind::ViewType } else { SyntaxKind::ArrayType });
+        self.bump();
+        if view {
+            self.bump();
+        }
+        self.ty();
+        if self.expect(TokenKind::Semicolon, "';' in array type") {
+            self.nat_expr();
+        }
+        self.expect(TokenKind::RBracket, "']'");
+        if view {
+            self.expect(TokenKind::RBracket, "']'");
+        }
+        self.finish_node();
+    }
+
+    fn tuple_type(&mut self) {
+        self.start_node(SyntaxKind::TupleType);
+        self.bump();
+        self.list(TokenKind::RParen, "tuple elements", |parser| parser.ty());
+        self.expect(TokenKind::RParen, "')'");
+        self.finish_node();
+    }
+
+    // <T, n/2, X<16>>, the arguments are types or nat expressions
+    fn generic_arg_list(&mut self) {
+        self.start_node(SyntaxKind::GenericArgList);
+        self.bump();
+        self.list(TokenKind::Gt, "generic arguments", |parser| parser.generic_arg());
+        self.expect(TokenKind::Gt, "'>'");
+        self.finish_node();
+    }
+
+    fn generic_arg(&mut self) {
+        let is_type = self.at_any(&[TokenKind::Amp, TokenKind::LBracket])
+            || (self.at(TokenKind::Ident) && self.nth(1) == Some(TokenKind::Lt))
+            || (self.at(TokenKind::LParen) && self.nth(1) == Some(TokenKind::RParen));
+        if is_type {
+            self.ty();
+        } else {
+            self.nat_expr();
+        }
+    }
+
+    fn nat_expr(&mut self) {
+        if !self.expr_bp(NAT_BINDING_POWER) {
+            self.error_and_bump("Expected nat expression", &[TokenKind::Comma, TokenKind::Gt, TokenKind::RBracket]);
+        }
+    }
+
+    // Statements
+
+    fn block(&mut self) {
+        self.start_node(SyntaxKind::Block);
+        self.bump();
+        while !self.at(TokenKind::RBrace) && !self.at_end() && !self.at_any(ITEM_START) {
+            let position = self.position;
+            self.stmt();
+            if self.position == position {
+                self.error_and_bump("Expected statement", &[]);
+                if self.position == position {
+                    break;
+                }
+            }
+        }
+        self.expect(TokenKind::RBrace, "'}'");
+        self.finish_node();
+    }
+
+    fn stmt(&mut self) {
+        if self.eat(TokenKind::Semicolon) {
+            return;
+        }
+        if self.at(TokenKind::Let) {
+            self.let_stmt();
+            return;
+        }
+
+        let checkpoint = self.checkpoint();
+        // statements like "for" end with their block, what follows is the next statement
+        let block_like = self.at_any(&[TokenKind::If, TokenKind::For, TokenKind::While, TokenKind::Sched, TokenKind::Split, TokenKind::LBrace, TokenKind::Unsafe]);
+        let parsed = if block_like { self.primary_expr() } else { self.expr() };
+        if !parsed {
+            return;
+        }
+        if self.at(TokenKind::RBrace) {
+            return; // the value of the block
+        }
+        self.start_node_at(checkpoint, SyntaxKind::ExprStmt);
+        if !self.eat(TokenKind::Semicolon) && !block_like {
+            self.error("Expected ';'");
+        }
+        self.finish_node();
+    }
+
+    // let mut name: type = expr;
+    fn let_stmt(&mut self) {
+        self.start_node(SyntaxKind::LetStmt);
+        self.bump();
+        self.eat(TokenKind::Mut);
+        self.name("variable name");
+        if self.eat(TokenKind::Colon) {
+            self.ty();
+        }
+        if self.eat(TokenKind::Eq) && !self.expr() {
+            self.error("Expected expression");
+        }
+        self.expect(TokenKind::Semicolon, "';'");
+        self.finish_node();
+    }
+
+    // Expressions
+
+    fn expr(&mut self) -> bool {
+        self.expr_bp(0)
+    }
+
+    // Pratt parser, returns whether there was an expression at all
+    fn expr_bp(&mut self, min_binding_power: u8) -> bool {
+        if !self.enter() {
+            return true;
+        }
+        let checkpoint = self.checkpoint();
+        if !self.prefix_expr() {
+            self.exit();
+            return false;
+        }
+        // every operator of a left associative chain wraps the expression so far one level deeper
+        let mut levels = 0;
+        while let Some((left, right)) = self.current().and_then(infix_binding_power) {
+            if left < min_binding_power || !self.enter() {
+                break;
+            }
+            levels += 1;
+            self.start_node_at(checkpoint, SyntaxKind::BinaryExpr);
+            self.bump();
+            if !self.expr_bp(right) {
+                self.error("Expected expression after operator");
+            }
+            self.finish_node();
+        }
+        for _ in 0..=levels {
+            self.exit();
+        }
+        true
+    }
+
+    fn prefix_expr(&mut self) -> bool {
+        match self.current() {
+            Some(TokenKind::Minus | TokenKind::Bang | TokenKind::Star) => {
+                self.start_node(SyntaxKind::PrefixExpr);
+                self.bump();
+                if !self.expr_bp(PREFIX_BINDING_POWER) {
+                    self.error("Expected expression");
+                }
+                self.finish_node();
+                true
+            },
+            // &uniq x, &'r shrd x
+            Some(TokenKind::Amp) => {
+                self.start_node(SyntaxKind::BorrowExpr);
+                self.bump();
+                self.eat(TokenKind::Lifetime);
+                if !self.eat(TokenKind::Uniq) && !self.eat(TokenKind::Shrd) {
+                    self.error("Expected 'uniq' or 'shrd'");
+                }
+                if !self.expr_bp(PREFIX_BINDING_POWER) {
+                    self.error("Expected expression");
+                }
+                self.finish_node();
+                true
+            },
+            _ => self.postfix_expr()
+        }
+    }
+
+    fn postfix_expr(&mut self) -> bool {
+        let checkpoint = self.checkpoint();
+        if !self.primary_expr() {
+            return false;
+        }
+        // like binary expressions, every call, index or field goes one level deeper
+        let mut levels = 0;
+        loop {
+            let postfix = matches!(self.current(), Some(TokenKind::LParen | TokenKind::LBracket | TokenKind::Dot));
+            if postfix {
+                if !self.enter() {
+                    break;
+                }
+                levels += 1;
+            }
+            match self.current() {
+                Some(TokenKind::LParen) => {
+                    self.start_node_at(checkpoint, SyntaxKind::CallExpr);
+                    self.arg_list();
+                    self.finish_node();
+                },
+                Some(TokenKind::LBracket) if self.nth(1) == Some(TokenKind::LBracket) => {
+                    self.start_node_at(checkpoint, SyntaxKind::SelectExpr);
+                    self.bump();
+                    self.bump();
+                    if !self.expr() {
+                        self.error("Expected execution resource to select with");
+                    }
+                    self.expect(TokenKind::RBracket, "']'");
+                    self.expect(TokenKind::RBracket, "']'");
+                    self.finish_node();
+                },
+                Some(TokenKind::LBracket) => {
+                    self.start_node_at(checkpoint, SyntaxKind::IndexExpr);
+                    self.bump();
+                    if !self.expr() {
+                        self.error("Expected index");
+                    }
+                    self.expect(TokenKind::RBracket, "']'");
+                    self.finish_node();
+                },
+                // x.field, x.0 or the view x.grp::<32>
+                Some(TokenKind::Dot) => {
+                    let view = self.nth(2) == Some(TokenKind::ColonColon);
+                    self.start_node_at(checkpoint, if view { SyntaxKind::ViewExpr } else { SyntaxKind::FieldExpr });
+                    self.bump();
+                    if self.at(TokenKind::Ident) {
+                        self.name_ref();
+                    } else if !self.eat(TokenKind::Integer) {
+                        self.error("Expected field or view name");
+                    }
+                    if view {
+                        self.bump();
+                        if self.at(TokenKind::Lt) {
+                            self.generic_arg_list();
+                        } else {
+                            self.error("Expected generic arguments");
+                        }
+                    }
+                    self.finish_node();
+                },
+                _ => break
+            }
+        }
+        for _ in 0..levels {
+            self.exit();
+        }
+        true
+    }
+
+    fn arg_list(&mut self) {
+        self.start_node(SyntaxKind::ArgList);
+        self.bump();
+        self.list(TokenKind::RParen, "arguments", |parser| {
+            if !parser.expr() {
+                parser.error_and_bump("Expected argument", &[TokenKind::Comma, TokenKind::RParen]);
+            }
+        });
+        self.expect(TokenKind::RParen, "')'");
+        self.finish_node();
+    }
+
+    fn primary_expr(&mut self) -> bool {
+        if !self.enter() {
+            return true;
+        }
+        match self.current() {
+            Some(kind) if LITERALS.contains(&kind) => {
+                self.start_node(SyntaxKind::Literal);
+                self.bump();
+                self.finish_node();
+            },
+            Some(TokenKind::Ident) => self.path_expr(),
+            Some(TokenKind::LParen) => self.paren_expr(),
+            Some(TokenKind::LBracket) => {
+                self.start_node(SyntaxKind::ArrayExpr);
+                self.bump();
+                self.list(TokenKind::RBracket, "array elements", |parser| {
+                    if !parser.expr() {
+                        parser.error_and_bump("Expected array element", &[TokenKind::Comma, TokenKind::RBracket]);
+                    }
+                });
+                self.expect(TokenKind::RBracket, "']'");
+                self.finish_node();
+            },
+            Some(TokenKind::LBrace) => {
+                self.start_node(SyntaxKind::BlockExpr);
+                self.block();
+                self.finish_node();
+            },
+            Some(TokenKind::Unsafe) => {
+                self.start_node(SyntaxKind::UnsafeExpr);
+                self.bump();
+                self.block_or_error();
+                self.finish_node();
+            },
+            Some(TokenKind::If) => self.if_expr(),
+            Some(TokenKind::For) => {
+                // for i in 0..n { ... }
+                self.start_node(SyntaxKind::ForExpr);
+                self.bump();
+                self.name("loop variable");
+                self.expect(TokenKind::In, "'in'");
+                if !self.expr() {
+                    self.error("Expected expression to iterate over");
+                }
+                self.block_or_error();
+                self.finish_node();
+            },
+            Some(TokenKind::While) => {
+                self.start_node(SyntaxKind::WhileExpr);
+                self.bump();
+                if !self.expr() {
+                    self.error("Expected condition");
+                }
+                self.block_or_error();
+                self.finish_node();
+            },
+            Some(TokenKind::Sched) => self.sched_expr(),
+            Some(TokenKind::Split) => self.split_expr(),
+            Some(TokenKind::Sync) => {
+                // sync or sync(block)
+                self.start_node(SyntaxKind::SyncExpr);
+                self.bump();
+                if self.at(TokenKind::LParen) {
+                    self.arg_list();
+                }
+                self.finish_node();
+            },
+            Some(TokenKind::Pipe | TokenKind::PipePipe) => self.closure_expr(),
+            Some(kind) if EXEC.contains(&kind) || MEMORY.contains(&kind) => {
+                // resources are values as well, e.g. the grid to schedule over
+                self.start_node(SyntaxKind::PathExpr);
+                self.bump();
+                self.finish_node();
+            },
+            _ => {
+                self.exit();
+                return false;
+            }
+        }
+        self.exit();
+        true
+    }
+
+    // name, name::<args> or the kernel launch name::<<<grid, block>>>(args)
+    fn path_expr(&mut self) {
+        let checkpoint = self.checkpoint();
+        self.start_node(SyntaxKind::PathExpr);
+        self.name_ref();
+        let launch = self.at(TokenKind::ColonColon) && (1..=3).all(|n| self.nth(n) == Some(TokenKind::Lt));
+        if self.at(TokenKind::ColonColon) && !launch {
+            self.bump();
+            if self.at(TokenKind::Lt) {
+                self.generic_arg_list();
+            } else {
+                self.error("Expected generic arguments");
+            }
+        }
+        self.finish_node();
+
+        if launch {
+            self.start_node_at(checkpoint, SyntaxKind::KernelLaunch);
+            self.start_node(SyntaxKind::LaunchConfig);
+            for _ in 0..4 {
+                self.bump(); // "::<<<"
+            }
+            self.list(TokenKind::Gt, "launch configuration", |parser| {
+                if parser.at(TokenKind::Semicolon) {
+                    parser.bump(); // separates the grid from the shared memory configuration
+                }
+                parser.generic_arg();
+            });
+            for _ in 0..3 {
+                self.expect(TokenKind::Gt, "'>>>'");
+            }
+            self.finish_node();
+            if self.at(TokenKind::LParen) {
+                self.arg_list();
+            } else {
+                self.error("Expected kernel arguments");
+            }
+            self.finish_node();
+        }
+    }
+
+    // (), (x) or (x, y,)
+    fn paren_expr(&mut self) {
+        let checkpoint = self.checkpoint();
+        self.bump();
+        let mut elements = 0;
+        let mut trailing_comma = false;
+        while !self.at(TokenKind::RParen) && !self.at_end() && !self.at_any(ITEM_START) {
+            if !self.expr() {
+                self.error_and_bump("Expected expression", &[TokenKind::Comma, TokenKind::RParen]);
+                if !self.at(TokenKind::Comma) {
+                    break;
+                }
+            }
+            elements += 1;
+            trailing_comma = self.eat(TokenKind::Comma);
+            if !trailing_comma {
+                break;
+            }
+        }
+        self.expect(TokenKind::RParen, "')'");
+        let kind = if elements == 1 && !trailing_comma { SyntaxKind::ParenExpr } else { SyntaxKind::TupleExpr };
+        self.start_node_at(checkpoint, kind);
+        self.finish_node();
+    }
+
+    fn block_or_error(&mut self) {
+        if self.at(TokenKind::LBrace) {
+            self.block();
+        } else {
+            self.error("Expected block");
+        }
+    }
+
+    fn if_expr(&mut self) {
+        if !self.enter() {
+            return;
+        }
+        self.start_node(SyntaxKind::IfExpr);
+        self.bump();
+        if !self.expr() {
+            self.error("Expected condition");
+        }
+        self.block_or_error();
+        if self.eat(TokenKind::Else) {
+            if self.at(TokenKind::If) {
+                self.if_expr();
+            } else {
+                self.block_or_error();
+            }
+        }
+        self.finish_node();
+        self.exit();
+    }
+
+    // Optional dimension like (X) or (XY) of sched and split
+    fn dimension(&mut self) {
+        if self.eat(TokenKind::LParen) {
+            if self.at(TokenKind::Ident) {
+                self.name_ref();
+            } else {
+                self.error("Expected dimension");
+            }
+            self.expect(TokenKind::RParen, "')'");
+        }
+    }
+
+    // sched(X) block in grid { ... }
+    fn sched_expr(&mut self) {
+        self.start_node(SyntaxKind::SchedExpr);
+        self.bump();
+        self.dimension();
+        self.name("name of the scheduled execution resource");
+        self.expect(TokenKind::In, "'in'");
+        if !self.expr() {
+            self.error("Expected execution resource to schedule over");
+        }
+        self.block_or_error();
+        self.finish_node();
+    }
+
+    // split(X) 32 block { fst => { ... }, snd => { ... } }
+    fn split_expr(&mut self) {
+        self.start_node(SyntaxKind::SplitExpr);
+        self.bump();
+        self.dimension();
+        if !self.expr_bp(PREFIX_BINDING_POWER) {
+            self.error("Expected position to split at");
+        }
+        if !self.expr_bp(PREFIX_BINDING_POWER) {
+            self.error("Expected execution resource to split");
+        }
+        if self.expect(TokenKind::LBrace, "'{'") {
+            self.list(TokenKind::RBrace, "split arms", |parser| {
+                parser.start_node(SyntaxKind::SplitArm);
+                parser.name("name of the part");
+                parser.expect(TokenKind::FatArrow, "'=>'");
+                parser.block_or_error();
+                parser.finish_node();
+            });
+            self.expect(TokenKind::RBrace, "'}'");
+        }
+        self.finish_node();
+    }
+
+    // |grid, inputs| -[grid: gpu.grid<X<16>, X<32>>]-> () { ... }
+    fn closure_expr(&mut self) {
+        self.start_node(SyntaxKind::ClosureExpr);
+        self.start_node(SyntaxKind::ClosureParamList);
+        if !self.eat(TokenKind::PipePipe) {
+            self.bump();
+            self.list(TokenKind::Pipe, "closure parameters", |parser| {
+                parser.start_node(SyntaxKind::Param);
+                parser.eat(TokenKind::Mut);
+                parser.name("parameter name");
+                if parser.eat(TokenKind::Colon) {
+                    parser.ty();
+                }
+                parser.finish_node();
+            });
+            self.expect(TokenKind::Pipe, "'|'");
+        }
+        self.finish_node();
+        if self.at(TokenKind::Minus) && self.nth(1) == Some(TokenKind::LBracket) {
+            self.exec_annotation();
+            self.ret_type();
+        } else if self.eat(TokenKind::Arrow) {
+            self.ret_type();
+        }
+        if !self.expr() {
+            self.error("Expected closure body");
+        }
+        self.finish_node();
+    }
+}
+
+// Parses a whole Descend file, the tree covers the text even if it's full of errors
+pub fn parse(text: &str) -> SyntaxTree {
+    let lexed = lex(text);
+    let mut parser = Parser { text, tokens: lexed.tokens, position: 0, builder: Builder::default(), errors: Vec::new(), delimiters: Vec::new(), depth: Depth::default() };
+    parser.builder.start_node(SyntaxKind::SourceFile);
+    parser.source_file();
+
+    let mut errors: Vec<SyntaxError> = lexed.errors.into_iter().map(|error| SyntaxError { span: error.span, message: error.message, code: "invalid-token", related: None }).collect();
+    errors.extend(parser.errors);
+    errors.sort_by_key(|error| error.span.start);
+    SyntaxTree { text: text.to_string(), root: parser.builder.finish(), errors }
+}
+
+#[cfg(test)]
+const KERNEL: &str = "// scales a vector on the gpu
+struct Pair<n: nat> { fst: [f64; n], snd: [f64; n] }
+
+fn scale_vec<n: nat, r: prv>(vec: &r uniq gpu.global [f64; n]) -[grid: gpu.grid<X<n/1024>, X<1024>>]-> () {
+    sched(X) block in grid {
+        sched(X) thread in block {
+            let v = &uniq (*vec).to_view.grp::<1024>[[block]][[thread]];
+            *v = *v * 3.0;
+            sync
+        }
+    }
+}
+
+fn main() -[t: cpu.thread]-> () {
+    let mut d_vec = gpu_alloc_copy(&uniq gpu, &shrd h_vec);
+    scale_vec::<<<X<32>, X<1024>>>>(&uniq d_vec);
+    split(X) 16 block { fst => { f(); }, snd => { g(); } }
+}
+";
+
+#[test]
+fn test_parse() {
+    use crate::{ast::{GenericParam, SourceFile}, syntax::NodeId};
+
+    let tree = parse(KERNEL);
+    assert_eq!(tree.errors, vec![]);
+    // lossless, the tokens are the text
+    assert_eq!(tree.root.all_tokens().iter().map(|token| tree.text(&token.span)).collect::<String>(), KERNEL);
+
+    let file = SourceFile::cast(&tree.root).unwrap();
+    let names: Vec<&str> = file.functions().filter_map(|function| function.name()).map(|name| tree.text(&name.token().span)).collect();
+    assert_eq!(names, vec!["scale_vec", "main"]);
+    let scale_vec = file.functions().next().unwrap();
+    let kinds: Vec<Option<TokenKind>> = scale_vec.generic_params().map(GenericParam::kind).collect();
+    assert_eq!(kinds, vec![Some(TokenKind::Nat), Some(TokenKind::Prv)]);
+    assert_eq!(scale_vec.params().next().unwrap().ty().unwrap().kind, SyntaxKind::RefType);
+    assert!(scale_vec.exec().unwrap().exec_type().unwrap().token(TokenKind::GpuGrid).is_some());
+    assert_eq!(file.structs().next().unwrap().fields().count(), 2);
+
+    let kinds: Vec<SyntaxKind> = tree.root.descendants().iter().map(|node| node.kind).collect();
+    for kind in [SyntaxKind::SchedExpr, SyntaxKind::SelectExpr, SyntaxKind::ViewExpr, SyntaxKind::KernelLaunch, SyntaxKind::SplitArm, SyntaxKind::SyncExpr] {
+        assert!(kinds.contains(&kind), "{kind:?} missing");
+    }
+
+    // the ids are the preorder numbers
+    for (i, node) in tree.root.descendants().into_iter().enumerate() {
+        assert_eq!(node.id, NodeId(i as u32));
+        assert_eq!(tree.find(node.id), Some(node));
+    }
+}
+
+#[test]
+fn test_parse_recovery() {
+    let text = "fn broken(x: &uniq [i32; n] y: i32) -[t: gpu.thread]-> () {\n    let z = ;\n    sched thread in {\n}\n\nfn fine() { }\n";
+    let tree = parse(text);
+    assert!(!tree.errors.is_empty());
+    assert_eq!(tree.root.all_tokens().iter().map(|token| tree.text(&token.span)).collect::<String>(), text);
+
+    // the function after the unclosed block is unaffected
+    let file = crate::ast::SourceFile::cast(&tree.root).unwrap();
+    let functions: Vec<crate::ast::FnDecl> = file.functions().collect();
+    assert_eq!(functions.len(), 2);
+    assert_eq!(functions[0].params().count(), 2);
+    assert_eq!(tree.text(&functions[1].name().unwrap().token().span), "fine");
+    assert!(functions[1].body().is_some());
+
+    // the body of broken isn't closed before fine starts
+    let unclosed = tree.errors.iter().find(|error| error.message == "Expected '}'").unwrap();
+    assert_eq!(unclosed.related.as_ref().map(|(span, _)| tree.text(span)), Some("{"));
+    assert_eq!(unclosed.related.as_ref().unwrap().0.start, text.find("{\n").unwrap());
+}
+
+
+#[test]
+fn test_parse_deep_nesting() {
+    let depth = 100_000;
+    let text = format!("fn f() {{ let x = {}1{}; let y: {}i32; }}\nfn g() {{ }}\n", "(".repeat(depth), ")".repeat(depth), "&uniq cpu.mem ".repeat(depth));
+    let tree = parse(&text);
+    assert_eq!(tree.root.all_tokens().iter().map(|token| tree.text(&token.span)).collect::<String>(), text);
+    let messages: Vec<&str> = tree.errors.iter().map(|error| error.message.as_str()).collect();
+    assert_eq!(messages, vec!["Code is nested too deeply", "Code is nested too deeply"]);
+
+    // the rest of the file is parsed as usual
+    let file = crate::ast::SourceFile::cast(&tree.root).unwrap();
+    assert_eq!(file.functions().count(), 2);
+}
+
+#[test]
+fn test_parse_long_chains() {
+    let terms = 100_000;
+    let text = format!("fn f() {{ let x: i32 = 1{}; let y = a{}; }}\nfn g() {{ }}\n", " + 1".repeat(terms), ".b[0]()".repeat(terms / 3));
+    let tree = parse(&text);
+    assert_eq!(tree.root.all_tokens().iter().map(|token| tree.text(&token.span)).collect::<String>(), text);
+    assert!(tree.errors.len() >= 2 && tree.errors.iter().all(|error| error.message == "Code is nested too deeply"));
+
+    // the chains are cut off, so the tree is no deeper than nested code
+    for start in [text.find("1 +").unwrap(), text.find("a.b").unwrap()] {
+        assert!(tree.root.ancestors_at(start).len() < Depth::MAX + 10);
+        assert_eq!(tree.root.token_at(start).map(|token| tree.text(&token.span)), Some(&text[start..start + 1]));
+    }
+    assert!(tree.root.descendants().len() > Depth::MAX);
+    let file = crate::ast::SourceFile::cast(&tree.root).unwrap();
+    assert_eq!(file.functions().count(), 2);
+    drop(tree);
+}
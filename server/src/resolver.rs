@@ -0,0 +1,503 @@
+use std::{collections::HashMap, ops::Range};
+
+use crate::{ast::GenericParam, lexer::TokenKind, syntax::{Depth, NodeId, SyntaxKind, SyntaxNode, SyntaxTree}};
+
+// Types every Descend program can use without declaring them
+const BUILTIN_TYPES: &[&str] = &[
+    "bool", "i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64", "usize", "f32", "f64", "Gpu",
+    "X", "Y", "Z", "XY", "XZ", "YZ", "XYZ" // dimensions of execution resources
+];
+
+// Functions predeclared by the Descend standard library
+const BUILTIN_FUNCTIONS: &[&str] = &["gpu_device", "gpu_alloc_copy", "copy_to_host", "copy_to_gpu", "create_array", "shared_alloc", "exec"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScopeId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DefinitionId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GenericKind {
+    Nat,
+    Mem,
+    Ty,
+    Dty,
+    Prv
+}
+
+impl GenericKind {
+    fn from_token(kind: TokenKind) -> Option<GenericKind> {
+        Some(match kind {
+            TokenKind::Nat => GenericKind::Nat,
+            TokenKind::Mem => GenericKind::Mem,
+            TokenKind::Ty => GenericKind::Ty,
+            TokenKind::Dty => GenericKind::Dty,
+            TokenKind::Prv => GenericKind::Prv,
+            _ => return None
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionKind {
+    Function,
+    Struct,
+    Field,
+    Generic(Option<GenericKind>), // the kind is missing in half-typed code
+    ExecResource, // bound by an execution annotation, sched or split
+    Param,
+    Local,
+    LoopVariable
+}
+
+// Names of different namespaces don't see each other, e.g. a struct can't be used as a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    Value, // including nat generics, which appear in expressions like n/2
+    Type,
+    Provenance,
+    Memory
+}
+
+impl Namespace {
+    fn description(self) -> &'static str {
+        match self {
+            Namespace::Value => "value",
+            Namespace::Type => "type",
+            Namespace::Provenance => "provenance",
+            Namespace::Memory => "memory"
+        }
+    }
+}
+
+impl DefinitionKind {
+    pub fn is_in(self, namespace: Namespace) -> bool {
+        match (self, namespace) {
+            (DefinitionKind::Generic(None), _) => true,
+            (DefinitionKind::Generic(Some(kind)), _) => matches!((kind, namespace),
+                (GenericKind::Nat, Namespace::Value) | (GenericKind::Ty | GenericKind::Dty, Namespace::Type)
+                | (GenericKind::Prv, Namespace::Provenance) | (GenericKind::Mem, Namespace::Memory)),
+            (DefinitionKind::Struct, Namespace::Type) => true,
+            (DefinitionKind::Function | DefinitionKind::ExecResource | DefinitionKind::Param | DefinitionKind::Local | DefinitionKind::LoopVariable, Namespace::Value) => true,
+            _ => false
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Definition {
+    pub name: String,
+    pub kind: DefinitionKind,
+    pub span: Range<usize>, // of the name
+    pub node: NodeId, // the Name node
+    pub scope: ScopeId,
+    pub visible_from: usize // offset after which the definition is in scope, e.g. the end of a let statement
+}
+
+// Region of the text in which the definitions are visible, nested in its parent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scope {
+    pub parent: Option<ScopeId>,
+    pub span: Range<usize>,
+    pub definitions: Vec<DefinitionId>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveError {
+    pub span: Range<usize>,
+    pub message: String,
+    pub code: &'static str, // "unresolved-name" or "duplicate-name"
+    pub related: Option<(Range<usize>, String)> // the previous definition of a duplicate
+}
+
+// What the identifiers of a syntax tree refer to. The scopes are in preorder, so nested scopes come after their parent.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resolution {
+    pub scopes: Vec<Scope>,
+    pub definitions: Vec<Definition>,
+    pub definitions_by_node: HashMap<NodeId, DefinitionId>, // of the Name nodes
+    pub references: HashMap<NodeId, DefinitionId>, // of the NameRef nodes, builtins and unresolved names are missing
+    pub errors: Vec<ResolveError>
+}
+
+impl Resolution {
+    pub fn definition(&self, id: DefinitionId) -> &Definition {
+        &self.definitions[id.0 as usize]
+    }
+
+    pub fn scope(&self, id: ScopeId) -> &Scope {
+        &self.scopes[id.0 as usize]
+    }
+
+    // The definition a Name or NameRef node stands for
+    pub fn definition_of(&self, node: NodeId) -> Option<DefinitionId> {
+        self.definitions_by_node.get(&node).or_else(|| self.references.get(&node)).copied()
+    }
+
+    // The definition of the identifier at the offset, be it the declaration itself or a reference to it
+    pub fn definition_at(&self, tree: &SyntaxTree, offset: usize) -> Option<DefinitionId> {
+        let token = tree.root.token_at(offset)?;
+        let ancestors = tree.root.ancestors_at(token.span.start);
+        let node = ancestors.last().filter(|node| matches!(node.kind, SyntaxKind::Name | SyntaxKind::NameRef))?;
+        self.definition_of(node.id)
+    }
+
+    pub fn references_to(&self, definition: DefinitionId) -> impl Iterator<Item = NodeId> + '_ {
+        self.references.iter().filter(move |(_, target)| **target == definition).map(|(node, _)| *node)
+    }
+
+    // Innermost scope containing the offset, the scope of the whole file if there's none
+    pub fn scope_at(&self, offset: usize) -> Option<ScopeId> {
+        let innermost = self.scopes.iter().rposition(|scope| scope.span.start <= offset && offset <= scope.span.end).unwrap_or(0);
+        (!self.scopes.is_empty()).then_some(ScopeId(innermost as u32))
+    }
+
+    // Definitions that can be referred to at the offset, inner ones shadow outer ones of the same name
+    pub fn visible_at(&self, offset: usize) -> Vec<&Definition> {
+        let mut visible: Vec<&Definition> = Vec::new();
+        let mut scope = self.scope_at(offset);
+        while let Some(id) = scope {
+            let definitions = self.scope(id).definitions.iter().rev().map(|definition| self.definition(*definition));
+            for definition in definitions.filter(|definition| definition.visible_from <= offset) {
+                if !visible.iter().any(|seen| seen.name == definition.name && seen.kind == definition.kind) {
+                    visible.push(definition);
+                }
+            }
+            scope = self.scope(id).parent;
+        }
+        visible
+    }
+}
+
+struct Resolver<'t> {
+    tree: &'t SyntaxTree,
+    resolution: Resolution,
+    scope: Option<ScopeId>, // the current one
+    depth: Depth // of the nodes being resolved, the parser reports the ones nested too deeply
+}
+
+impl<'t> Resolver<'t> {
+    fn text(&self, node: &SyntaxNode) -> &'t str {
+        self.tree.text(&node.span)
+    }
+
+    fn enter(&mut self, span: Range<usize>) {
+        let id = ScopeId(self.resolution.scopes.len() as u32);
+        self.resolution.scopes.push(Scope { parent: self.scope, span, definitions: Vec::new() });
+        self.scope = Some(id);
+    }
+
+    fn exit(&mut self) {
+        self.scope = self.scope.and_then(|scope| self.resolution.scope(scope).parent);
+    }
+
+    // Adds the definition of the Name node to the current scope. Shadowing is only allowed for
+    // local variables, the other names have to be unique in their scope.
+    fn define(&mut self, name: Option<&SyntaxNode>, kind: DefinitionKind, visible_from: usize) {
+        let (Some(name), Some(scope)) = (name, self.scope) else {
+            return;
+        };
+        let text = self.text(name).trim().to_string();
+        if text.is_empty() {
+            return;
+        }
+
+        // fields are in a scope with the generic parameters of their struct, but don't clash with them
+        let clashes = |definition: &Definition| definition.kind != DefinitionKind::Local
+            && (definition.kind == DefinitionKind::Field) == (kind == DefinitionKind::Field);
+        let previous = self.resolution.scope(scope).definitions.iter()
+            .map(|id| self.resolution.definition(*id))
+            .find(|definition| definition.name == text && kind != DefinitionKind::Local && clashes(definition));
+        if let Some(previous) = previous {
+            let span = previous.span.clone();
+            self.resolution.errors.push(ResolveError {
+                span: name.span.clone(),
+                message: format!("The name `{text}` is defined multiple times"),
+                code: "duplicate-name",
+                related: Some((span, format!("Previous definition of `{text}`")))
+            });
+        }
+
+        let id = DefinitionId(self.resolution.definitions.len() as u32);
+        self.resolution.definitions.push(Definition { name: text, kind, span: name.span.clone(), node: name.id, scope, visible_from });
+        self.resolution.definitions_by_node.insert(name.id, id);
+        self.resolution.scopes[scope.0 as usize].definitions.push(id);
+    }
+
+    fn lookup(&self, name: &str, namespaces: &[Namespace], offset: usize) -> Option<DefinitionId> {
+        let mut scope = self.scope;
+        while let Some(id) = scope {
+            let found = self.resolution.scope(id).definitions.iter().rev().copied().find(|definition| {
+                let definition = self.resolution.definition(*definition);
+                definition.name == name && namespaces.iter().any(|namespace| definition.kind.is_in(*namespace)) && definition.visible_from <= offset
+            });
+            if found.is_some() {
+                return found;
+            }
+            scope = self.resolution.scope(id).parent;
+        }
+        None
+    }
+
+    // Resolves the NameRef to a definition in one of the namespaces
+    fn reference(&mut self, name_ref: &SyntaxNode, namespaces: &[Namespace]) {
+        let name = self.text(name_ref).trim();
+        if let Some(definition) = self.lookup(name, namespaces, name_ref.span.start) {
+            self.resolution.references.insert(name_ref.id, definition);
+            return;
+        }
+        let builtin = namespaces.iter().any(|namespace| match namespace {
+            Namespace::Type => BUILTIN_TYPES.contains(&name),
+            Namespace::Value => BUILTIN_FUNCTIONS.contains(&name),
+            _ => false
+        });
+        if !builtin {
+            let description: Vec<&str> = namespaces.iter().map(|namespace| namespace.description()).collect();
+            self.resolution.errors.push(ResolveError {
+                span: name_ref.span.clone(),
+                message: format!("Cannot find {} `{name}` in this scope", description.join(" or ")),
+                code: "unresolved-name",
+                related: None
+            });
+        }
+    }
+
+    // Resolves the references of the subtree, entering the scopes it introduces
+    fn node(&mut self, node: &SyntaxNode) {
+        if !self.depth.enter() {
+            return;
+        }
+        self.node_of_kind(node);
+        self.depth.exit();
+    }
+
+    fn node_of_kind(&mut self, node: &SyntaxNode) {
+        match node.kind {
+            SyntaxKind::SourceFile => self.source_file(node),
+            SyntaxKind::FnDecl => self.fn_decl(node),
+            SyntaxKind::StructDecl => self.struct_decl(node),
+            SyntaxKind::Block => {
+                self.enter(node.span.clone());
+                self.children(node);
+                self.exit();
+            },
+            SyntaxKind::LetStmt => {
+                // the variable is only visible after the statement, so "let x = x;" refers to an outer x
+                self.children(node);
+                self.define(node.node(SyntaxKind::Name), DefinitionKind::Local, node.span.end);
+            },
+            SyntaxKind::ForExpr => self.binder(node, DefinitionKind::LoopVariable),
+            SyntaxKind::SchedExpr => self.binder(node, DefinitionKind::ExecResource),
+            SyntaxKind::SplitArm => self.binder(node, DefinitionKind::ExecResource),
+            SyntaxKind::ClosureExpr => {
+                self.enter(node.span.clone());
+                self.signature(node);
+                self.exit();
+            },
+            SyntaxKind::GenericArgList | SyntaxKind::LaunchConfig => self.generic_args(node),
+            SyntaxKind::NameRef => {
+                // the parent decides, see reference_namespace
+            },
+            _ => self.children(node)
+        }
+    }
+
+    // Resolves the children, the NameRefs among them according to the kind of the node
+    fn children(&mut self, node: &SyntaxNode) {
+        for child in node.nodes() {
+            if child.kind == SyntaxKind::NameRef {
+                if let Some(namespace) = reference_namespace(node, child) {
+                    self.reference(child, &[namespace]);
+                }
+            } else {
+                self.node(child);
+            }
+        }
+    }
+
+    // A lone name among generic arguments is parsed as a nat expression, but may as well be a type
+    fn generic_args(&mut self, node: &SyntaxNode) {
+        for child in node.nodes() {
+            let name_ref = child.node(SyntaxKind::NameRef).filter(|_| child.kind == SyntaxKind::PathExpr && child.nodes().count() == 1);
+            match name_ref {
+                Some(name_ref) => self.reference(name_ref, &[Namespace::Value, Namespace::Type]),
+                None => self.node(child)
+            }
+        }
+    }
+
+    fn source_file(&mut self, node: &SyntaxNode) {
+        self.enter(node.span.clone());
+        // items are visible in the whole file, no matter where they are declared
+        for item in node.nodes() {
+            match item.kind {
+                SyntaxKind::FnDecl => self.define(item.node(SyntaxKind::Name), DefinitionKind::Function, 0),
+                SyntaxKind::StructDecl => self.define(item.node(SyntaxKind::Name), DefinitionKind::Struct, 0),
+                _ => {}
+            }
+        }
+        self.children(node);
+        self.exit();
+    }
+
+    fn generic_params(&mut self, node: &SyntaxNode) {
+        for param in node.node(SyntaxKind::GenericParamList).into_iter().flat_map(SyntaxNode::nodes).filter_map(GenericParam::cast) {
+            let kind = param.kind().and_then(GenericKind::from_token);
+            self.define(param.name().map(|name| name.syntax()), DefinitionKind::Generic(kind), 0);
+        }
+    }
+
+    fn fn_decl(&mut self, node: &SyntaxNode) {
+        self.enter(node.span.clone());
+        self.generic_params(node);
+        self.signature(node);
+        self.exit();
+    }
+
+    // Parameters, execution annotation, return type and body of a function or closure, in the current scope
+    fn signature(&mut self, node: &SyntaxNode) {
+        if let Some(exec) = node.node(SyntaxKind::ExecAnnotation) {
+            self.children(exec);
+            self.define(exec.node(SyntaxKind::Name), DefinitionKind::ExecResource, 0);
+        }
+        let params = node.node(SyntaxKind::ParamList).or_else(|| node.node(SyntaxKind::ClosureParamList));
+        for param in params.into_iter().flat_map(SyntaxNode::nodes).filter(|param| param.kind == SyntaxKind::Param) {
+            self.children(param);
+            self.define(param.node(SyntaxKind::Name), DefinitionKind::Param, 0);
+        }
+        for child in node.nodes() {
+            if !matches!(child.kind, SyntaxKind::Name | SyntaxKind::GenericParamList | SyntaxKind::ExecAnnotation | SyntaxKind::ParamList | SyntaxKind::ClosureParamList) {
+                self.node(child);
+            }
+        }
+    }
+
+    fn struct_decl(&mut self, node: &SyntaxNode) {
+        self.enter(node.span.clone());
+        self.generic_params(node);
+        for field in node.nodes().filter(|field| field.kind == SyntaxKind::StructField) {
+            self.children(field);
+            self.define(field.node(SyntaxKind::Name), DefinitionKind::Field, 0);
+        }
+        self.exit();
+    }
+
+    // for, sched and split arms bind a name for their block, the rest is resolved outside of it
+    fn binder(&mut self, node: &SyntaxNode, kind: DefinitionKind) {
+        let block = node.node(SyntaxKind::Block);
+        for child in node.nodes().filter(|child| child.kind != SyntaxKind::Block) {
+            if child.kind == SyntaxKind::NameRef {
+                if let Some(namespace) = reference_namespace(node, child) {
+                    self.reference(child, &[namespace]);
+                }
+            } else {
+                self.node(child);
+            }
+        }
+        let span = block.map_or(node.span.end..node.span.end, |block| block.span.clone());
+        self.enter(span.clone());
+        self.define(node.node(SyntaxKind::Name), kind, span.start);
+        if let Some(block) = block {
+            self.children(block);
+        }
+        self.exit();
+    }
+}
+
+// What a NameRef child of the node refers to, None for names that aren't resolved lexically
+fn reference_namespace(parent: &SyntaxNode, name_ref: &SyntaxNode) -> Option<Namespace> {
+    match parent.kind {
+        SyntaxKind::PathExpr => Some(Namespace::Value),
+        SyntaxKind::PathType => Some(Namespace::Type),
+        SyntaxKind::AtType => Some(Namespace::Memory),
+        // &r uniq m T, the provenance comes before uniq or shrd and the memory after it
+        SyntaxKind::RefType => {
+            let ownership = parent.tokens().find(|token| matches!(token.kind, TokenKind::Uniq | TokenKind::Shrd));
+            match ownership {
+                Some(ownership) if name_ref.span.start < ownership.span.start => Some(Namespace::Provenance),
+                _ => Some(Namespace::Memory)
+            }
+        },
+        // fields and views depend on the type, dimensions like X of sched and split are fixed
+        _ => None
+    }
+}
+
+// Builds the scopes of a syntax tree and resolves the identifiers in it
+pub fn resolve(tree: &SyntaxTree) -> Resolution {
+    let mut resolver = Resolver { tree, resolution: Resolution::default(), scope: None, depth: Depth::default() };
+    resolver.node(&tree.root);
+    resolver.resolution.errors.sort_by_key(|error| error.span.start);
+    resolver.resolution
+}
+
+#[test]
+fn test_resolve() {
+    let text = "struct Pair<n: nat> { fst: [f64; n], snd: [f64; n] }
+
+fn scale<n: nat, r: prv, m: mem>(vec: &r uniq m [f64; n], pair: Pair<n>) -[grid: gpu.grid<X<n/32>, X<32>>]-> () {
+    sched(X) block in grid {
+        let x = n;
+        let x = x + 1;
+        split(X) 16 block { fst => { use(fst, x); }, snd => { use(block); } }
+    }
+}
+
+fn use(v: i32) {}
+";
+    let tree = crate::parser::parse(text);
+    assert_eq!(tree.errors, vec![]);
+    let resolution = resolve(&tree);
+    assert_eq!(resolution.errors, vec![]);
+
+    let at = |pattern: &str, nth: usize| text.match_indices(pattern).nth(nth).unwrap().0;
+    let definition = |offset: usize| resolution.definition(resolution.definition_at(&tree, offset).unwrap());
+
+    // the second x refers to the first one, the call to fst to the split arm
+    assert_eq!(definition(at("x + 1", 0)).span.start, at("x = n", 0));
+    assert_eq!(definition(at("fst, x", 0)).kind, DefinitionKind::ExecResource);
+    assert_eq!(definition(at("fst, x", 0)).span.start, at("fst =>", 0));
+    assert_eq!(definition(at("use(", 0)).kind, DefinitionKind::Function);
+    assert_eq!(definition(at("r uniq", 0)).kind, DefinitionKind::Generic(Some(GenericKind::Prv)));
+    assert_eq!(definition(at("m [f64", 0)).kind, DefinitionKind::Generic(Some(GenericKind::Mem)));
+    assert_eq!(definition(at("n/32", 0)).span.start, at("n: nat, r", 0));
+    assert_eq!(definition(at("Pair<n>", 0)).kind, DefinitionKind::Struct);
+
+    // the shadowed x isn't visible anymore at the split
+    let visible: Vec<&str> = resolution.visible_at(at("split", 0)).iter().map(|definition| definition.name.as_str()).collect();
+    assert_eq!(visible.iter().filter(|name| **name == "x").count(), 1);
+    assert!(visible.contains(&"block") && visible.contains(&"vec") && visible.contains(&"scale") && !visible.contains(&"fst"));
+}
+
+#[test]
+fn test_resolve_errors() {
+    let text = "fn f<n: nat, n: nat>(a: i32, a: &r shrd gpu.global T) { let b = c; let b = b; g(b); }\nfn f() {}\n";
+    let tree = crate::parser::parse(text);
+    let resolution = resolve(&tree);
+    let messages: Vec<&str> = resolution.errors.iter().map(|error| error.message.as_str()).collect();
+    assert_eq!(messages, vec![
+        "The name `n` is defined multiple times",
+        "The name `a` is defined multiple times",
+        "Cannot find provenance `r` in this scope",
+        "Cannot find type `T` in this scope",
+        "Cannot find value `c` in this scope",
+        "Cannot find value `g` in this scope",
+        "The name `f` is defined multiple times"
+    ]);
+    assert_eq!(resolution.errors[0].related.as_ref().unwrap().0, 5..6);
+}
+
+#[test]
+fn test_resolve_deep_nesting() {
+    // the parser cuts the chain off, but the tree is still deeper than the other passes go
+    let text = format!("fn f(a: i32) -> i32 {{ let x: i32 = a{}; x }}\n", " + a".repeat(100_000));
+    let tree = crate::parser::parse(&text);
+    let resolution = resolve(&tree);
+    assert_eq!(resolution.errors, vec![]);
+
+    // what comes after the chain is resolved as usual
+    let definition = |offset: usize| resolution.definition(resolution.definition_at(&tree, offset).unwrap());
+    assert_eq!(definition(text.find("x }").unwrap()).kind, DefinitionKind::Local);
+    assert_eq!(definition(text.find("a + a").unwrap() + 4 * 200).kind, DefinitionKind::Param);
+}
@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", untagged)]
 pub enum Id {
     AsInt(u64),
@@ -15,6 +15,75 @@ pub struct ClientInfo {
     pub version: Option<String>
 }
 
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceClientCapabilities {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub configuration: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub did_change_watched_files: Option<DidChangeWatchedFilesClientCapabilities>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub diagnostics: Option<DiagnosticWorkspaceClientCapabilities>
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticWorkspaceClientCapabilities {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub refresh_support: Option<bool> // whether the client understands "workspace/diagnostic/refresh"
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentClientCapabilities {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub diagnostic: Option<DiagnosticClientCapabilities> // present if the client pulls diagnostics
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticClientCapabilities {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub dynamic_registration: Option<bool>
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChangeWatchedFilesClientCapabilities {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub dynamic_registration: Option<bool>
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeneralClientCapabilities {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub position_encodings: Option<Vec<String>> // strings, as clients may support encodings unknown to us
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientCapabilities {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub workspace: Option<WorkspaceClientCapabilities>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub text_document: Option<TextDocumentClientCapabilities>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub general: Option<GeneralClientCapabilities>
+}
+
+// Unit in which Position::character is counted
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PositionEncodingKind {
+	#[serde(rename = "utf-8")]
+	Utf8,
+	#[default]
+	#[serde(rename = "utf-16")]
+	Utf16,
+	#[serde(rename = "utf-32")]
+	Utf32
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ServerInfo {
@@ -22,7 +91,7 @@ pub struct ServerInfo {
     pub version: String
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Position {
 	pub line: u32,
@@ -51,6 +120,13 @@ pub struct TextDocumentIdentifier {
 	pub uri: String
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionedTextDocumentIdentifier {
+	pub uri: String,
+	pub version: i32
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TextDocumentPositionParams {
@@ -130,24 +206,114 @@ pub struct WorkspaceEdit {
 	pub document_changes: Option<Vec<ChangeFile>>
 }
 
+// How the client sends changes of a document, serialized as its number
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TextDocumentSyncKind {
+	None = 0,
+	Full = 1, // the whole text with every change
+	#[default]
+	Incremental = 2 // only the changed ranges
+}
+
+impl Serialize for TextDocumentSyncKind {
+	fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_u32(*self as u32)
+	}
+}
+
+impl<'de> Deserialize<'de> for TextDocumentSyncKind {
+	fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		match u32::deserialize(deserializer)? {
+			0 => Ok(TextDocumentSyncKind::None),
+			1 => Ok(TextDocumentSyncKind::Full),
+			2 => Ok(TextDocumentSyncKind::Incremental),
+			kind => Err(serde::de::Error::custom(format!("unknown TextDocumentSyncKind {kind}")))
+		}
+	}
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TextDocumentSyncOptions {
 	pub open_close: bool,
-	pub change: u32
+	pub change: TextDocumentSyncKind,
+	pub will_save: bool,
+	pub will_save_wait_until: bool,
+	pub save: SaveOptions
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveOptions {
+	pub include_text: bool // the saved text is sent with "didSave"
+}
+
+pub struct TextDocumentSaveReason;
+
+impl TextDocumentSaveReason {
+	pub const MANUAL: u32 = 1;
+	pub const AFTER_DELAY: u32 = 2;
+	pub const FOCUS_OUT: u32 = 3;
+}
+
+// Options the client passes to "initialize", for everything that has to be known before the capabilities are sent
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InitializationOptions {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub text_document_sync: Option<TextDocumentSyncKind>
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ServerCapabilities {
+	pub position_encoding: PositionEncodingKind,
 	pub text_document_sync: TextDocumentSyncOptions,
-	pub hover_provider: bool
+	pub hover_provider: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub diagnostic_provider: Option<DiagnosticOptions>, // only for clients that pull diagnostics, the others get them pushed
+	pub workspace: WorkspaceServerCapabilities
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticOptions {
+	pub inter_file_dependencies: bool, // whether changing one document affects the diagnostics of others
+	pub workspace_diagnostics: bool
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceServerCapabilities {
+	pub workspace_folders: WorkspaceFoldersServerCapabilities
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceFoldersServerCapabilities {
+	pub supported: bool,
+	pub change_notifications: bool
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceFolder {
+	pub uri: String,
+	pub name: String
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceFoldersChangeEvent {
+	pub added: Vec<WorkspaceFolder>,
+	pub removed: Vec<WorkspaceFolder>
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TextDocumentContentChangeEvent {
-	pub range: Range,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub range: Option<Range>, // the whole document is replaced if missing
 	pub text: String
 }
 
@@ -167,5 +333,162 @@ pub struct MarkupContent {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Hover {
-	pub contents: MarkupContent
-}
\ No newline at end of file
+	pub contents: MarkupContent,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub range: Option<Range>
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigurationItem {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub scope_uri: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub section: Option<String>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigurationParams {
+	pub items: Vec<ConfigurationItem>
+}
+
+// Values of ShowMessageParams::typ
+pub struct MessageType;
+
+impl MessageType {
+	pub const ERROR: u32 = 1;
+	pub const WARNING: u32 = 2;
+	pub const INFO: u32 = 3;
+	pub const LOG: u32 = 4;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShowMessageParams {
+	#[serde(rename = "type")]
+	pub typ: u32,
+	pub message: String
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Registration {
+	pub id: String,
+	pub method: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub register_options: Option<serde_json::Value>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationParams {
+	pub registrations: Vec<Registration>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileSystemWatcher {
+	pub glob_pattern: String
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChangeWatchedFilesRegistrationOptions {
+	pub watchers: Vec<FileSystemWatcher>
+}
+
+pub struct FileChangeType;
+
+impl FileChangeType {
+	pub const CREATED: u32 = 1;
+	pub const CHANGED: u32 = 2;
+	pub const DELETED: u32 = 3;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileEvent {
+	pub uri: String,
+	#[serde(rename = "type")]
+	pub typ: u32
+}
+
+// Values of Diagnostic::severity
+pub struct DiagnosticSeverity;
+
+impl DiagnosticSeverity {
+	pub const ERROR: u32 = 1;
+	pub const WARNING: u32 = 2;
+	pub const INFORMATION: u32 = 3;
+	pub const HINT: u32 = 4;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticRelatedInformation {
+	pub location: Location,
+	pub message: String
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+	pub range: Range,
+	pub severity: u32,
+	pub code: String,
+	pub source: String,
+	pub message: String,
+	pub related_information: Vec<DiagnosticRelatedInformation>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublishDiagnosticsParams {
+	pub uri: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub version: Option<i32>,
+	pub diagnostics: Vec<Diagnostic>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FullDocumentDiagnosticReport {
+	pub result_id: String,
+	pub items: Vec<Diagnostic>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnchangedDocumentDiagnosticReport {
+	pub result_id: String
+}
+
+// Answer to "textDocument/diagnostic", the diagnostics are left out if they didn't change since the previous result
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum DocumentDiagnosticReport {
+	Full(FullDocumentDiagnosticReport),
+	Unchanged(UnchangedDocumentDiagnosticReport)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviousResultId {
+	pub uri: String,
+	pub value: String
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceDocumentDiagnosticReport {
+	pub uri: String,
+	pub version: Option<i32>, // null for files that aren't open, unlike the optional fields it's always present
+	#[serde(flatten)]
+	pub report: DocumentDiagnosticReport
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceDiagnosticReport {
+	pub items: Vec<WorkspaceDocumentDiagnosticReport>
+}
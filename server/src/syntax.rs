@@ -0,0 +1,310 @@
+use std::ops::Range;
+
+use crate::lexer::{Token, TokenKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SyntaxKind {
+    SourceFile,
+
+    // items
+    FnDecl,
+    StructDecl,
+    StructField,
+    GenericParamList,
+    GenericParam,
+    ParamList,
+    Param,
+    ExecAnnotation, // -[t: gpu.thread]->
+    RetType,
+
+    // types
+    PathType,
+    RefType,
+    ArrayType,
+    ViewType, // [[T; n]]
+    TupleType,
+    AtType, // T @ gpu.shared
+    ExecType, // gpu.grid<X<16>, X<32>>
+    GenericArgList,
+
+    // statements
+    Block,
+    LetStmt,
+    ExprStmt,
+
+    // expressions
+    Literal,
+    PathExpr,
+    ParenExpr,
+    TupleExpr,
+    ArrayExpr,
+    BinaryExpr,
+    PrefixExpr,
+    BorrowExpr, // &uniq x
+    CallExpr,
+    ArgList,
+    IndexExpr,
+    SelectExpr, // x[[thread]]
+    FieldExpr,
+    ViewExpr, // x.grp::<32>
+    KernelLaunch, // f::<<<X<16>, X<32>>>>(args)
+    LaunchConfig,
+    ClosureExpr,
+    ClosureParamList,
+    IfExpr,
+    ForExpr,
+    WhileExpr,
+    SchedExpr,
+    SplitExpr,
+    SplitArm,
+    SyncExpr,
+    UnsafeExpr,
+    BlockExpr,
+
+    Name, // identifier that's declared
+    NameRef, // identifier that refers to a declaration
+    Error // tokens the parser skipped
+}
+
+// Nodes are numbered in preorder, so the ids of a subtree are contiguous and the ids of one parse
+// identify the same nodes for as long as the document doesn't change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct NodeId(pub u32);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(Token)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxNode {
+    pub kind: SyntaxKind,
+    pub id: NodeId,
+    pub span: Range<usize>,
+    pub children: Vec<SyntaxElement>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub span: Range<usize>,
+    pub message: String,
+    pub code: &'static str, // "invalid-token" of the lexer or "syntax-error" of the parser
+    pub related: Option<(Range<usize>, String)> // e.g. the opening delimiter of a missing closing one
+}
+
+// Lossless concrete syntax tree, the tokens including trivia cover the whole text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxTree {
+    pub text: String,
+    pub root: SyntaxNode,
+    pub errors: Vec<SyntaxError> // of the lexer and the parser, ordered by their position
+}
+
+impl SyntaxNode {
+    pub fn nodes(&self) -> impl Iterator<Item = &SyntaxNode> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Node(node) => Some(node),
+            SyntaxElement::Token(_) => None
+        })
+    }
+
+    // Tokens that are direct children, without trivia
+    pub fn tokens(&self) -> impl Iterator<Item = &Token> {
+        self.children.iter().filter_map(|child| match child {
+            SyntaxElement::Token(token) if !token.kind.is_trivia() => Some(token),
+            _ => None
+        })
+    }
+
+    pub fn node(&self, kind: SyntaxKind) -> Option<&SyntaxNode> {
+        self.nodes().find(|node| node.kind == kind)
+    }
+
+    pub fn token(&self, kind: TokenKind) -> Option<&Token> {
+        self.tokens().find(|token| token.kind == kind)
+    }
+
+    // All tokens of the subtree in order, including trivia
+    pub fn all_tokens(&self) -> Vec<&Token> {
+        let mut tokens = Vec::new();
+        let mut stack = vec![self.children.iter()];
+        while let Some(children) = stack.last_mut() {
+            match children.next() {
+                Some(SyntaxElement::Node(node)) => stack.push(node.children.iter()),
+                Some(SyntaxElement::Token(token)) => tokens.push(token),
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        tokens
+    }
+
+    // All nodes of the subtree in preorder, starting with this one
+    pub fn descendants(&self) -> Vec<&SyntaxNode> {
+        let mut descendants = Vec::new();
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            descendants.push(node);
+            let children: Vec<&SyntaxNode> = node.nodes().collect();
+            stack.extend(children.into_iter().rev());
+        }
+        descendants
+    }
+
+    // The node with the id in this subtree, found by descending into the child whose ids contain it
+    pub fn find(&self, id: NodeId) -> Option<&SyntaxNode> {
+        let mut node = self;
+        loop {
+            if node.id == id {
+                return Some(node);
+            }
+            node = node.nodes().take_while(|child| child.id <= id).last()?;
+        }
+    }
+
+    // The nodes containing the offset, from this one down to the innermost
+    pub fn ancestors_at(&self, offset: usize) -> Vec<&SyntaxNode> {
+        let mut ancestors = vec![self];
+        let mut node = self;
+        while let Some(child) = node.nodes().find(|child| child.span.start <= offset && offset < child.span.end) {
+            ancestors.push(child);
+            node = child;
+        }
+        ancestors
+    }
+
+    // Innermost token containing the offset, or ending at it if there's none, e.g. at the end of an identifier
+    pub fn token_at(&self, offset: usize) -> Option<&Token> {
+        let mut node = self;
+        'descend: loop {
+            for child in &node.children {
+                match child {
+                    SyntaxElement::Node(child) if child.span.start <= offset && offset < child.span.end => {
+                        node = child;
+                        continue 'descend;
+                    },
+                    SyntaxElement::Token(token) if token.span.start <= offset && offset < token.span.end => return Some(token),
+                    _ => {}
+                }
+            }
+            return node.all_tokens().into_iter().rev().find(|token| token.span.end == offset && !token.kind.is_trivia());
+        }
+    }
+}
+
+impl SyntaxTree {
+    pub fn text(&self, span: &Range<usize>) -> &str {
+        &self.text[span.clone()]
+    }
+
+    pub fn find(&self, id: NodeId) -> Option<&SyntaxNode> {
+        self.root.find(id)
+    }
+}
+
+// Nesting depth of a recursive walk over the tree. Deeply nested code would overflow the stack of
+// the parser and of the passes after it, so they stop descending at Depth::MAX.
+#[derive(Debug, Default)]
+pub struct Depth(usize);
+
+impl Depth {
+    pub const MAX: usize = 256;
+
+    // Goes one level deeper, unless that's too deep
+    pub fn enter(&mut self) -> bool {
+        if self.0 >= Depth::MAX {
+            return false;
+        }
+        self.0 += 1;
+        true
+    }
+
+    pub fn exit(&mut self) {
+        self.0 = self.0.saturating_sub(1);
+    }
+}
+
+// Builds the tree bottom up, the parser announces the nodes and feeds the tokens in order
+#[derive(Default)]
+pub struct Builder {
+    stack: Vec<(SyntaxKind, Vec<SyntaxElement>)>,
+    position: usize // end of the last token
+}
+
+pub type Checkpoint = usize;
+
+impl Builder {
+    pub fn start_node(&mut self, kind: SyntaxKind) {
+        self.stack.push((kind, Vec::new()));
+    }
+
+    // Starts a node that contains the children added since the checkpoint, e.g. the left operand of a binary expression
+    pub fn start_node_at(&mut self, checkpoint: Checkpoint, kind: SyntaxKind) {
+        let children = match self.stack.last_mut() {
+            Some((_, children)) => children.split_off(checkpoint.min(children.len())),
+            None => Vec::new()
+        };
+        self.stack.push((kind, children));
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.stack.last().map_or(0, |(_, children)| children.len())
+    }
+
+    pub fn token(&mut self, token: Token) {
+        self.position = token.span.end;
+        if let Some((_, children)) = self.stack.last_mut() {
+            children.push(SyntaxElement::Token(token));
+        }
+    }
+
+    // The root is only finished by finish
+    pub fn finish_node(&mut self) {
+        if self.stack.len() < 2 {
+            return;
+        }
+        let Some((kind, children)) = self.stack.pop() else {
+            return;
+        };
+        let node = self.node(kind, children);
+        if let Some((_, parent)) = self.stack.last_mut() {
+            parent.push(SyntaxElement::Node(node));
+        }
+    }
+
+    fn node(&self, kind: SyntaxKind, children: Vec<SyntaxElement>) -> SyntaxNode {
+        let span_of = |element: &SyntaxElement| match element {
+            SyntaxElement::Node(node) => node.span.clone(),
+            SyntaxElement::Token(token) => token.span.clone()
+        };
+        let span = match (children.first(), children.last()) {
+            (Some(first), Some(last)) => span_of(first).start..span_of(last).end,
+            _ => self.position..self.position // missing node, e.g. of half-typed code
+        };
+        SyntaxNode { kind, id: NodeId::default(), span, children }
+    }
+
+    // Finishes the remaining nodes and numbers all of them
+    pub fn finish(mut self) -> SyntaxNode {
+        while self.stack.len() > 1 {
+            self.finish_node();
+        }
+        let (kind, children) = self.stack.pop().unwrap_or((SyntaxKind::SourceFile, Vec::new()));
+        let mut root = self.node(kind, children);
+
+        let mut next_id = 0u32;
+        let mut stack = vec![&mut root];
+        while let Some(node) = stack.pop() {
+            node.id = NodeId(next_id);
+            next_id += 1;
+            let children: Vec<&mut SyntaxNode> = node.children.iter_mut().filter_map(|child| match child {
+                SyntaxElement::Node(node) => Some(node),
+                SyntaxElement::Token(_) => None
+            }).collect();
+            stack.extend(children.into_iter().rev());
+        }
+        root
+    }
+}
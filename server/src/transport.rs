@@ -0,0 +1,104 @@
+use std::{io::{BufRead, BufReader, BufWriter, Write}, net::{TcpListener, TcpStream}};
+
+// Channel over which the raw messages are exchanged with the client, selected on the command line:
+//
+// --stdio                      stdin and stdout (default)
+// --socket=<port|host:port>    connect to the client listening on the socket, like vscode-languageclient expects it
+// --listen=<port|host:port>    wait for a single client to connect to the socket, e.g. for debugging proxies
+// --pipe=<path>                connect to the client listening on the Unix domain socket (vscode-languageclient's
+//                              TransportKind.pipe), which keeps stdout free for debug output
+#[derive(Debug, PartialEq)]
+pub enum Transport {
+    Stdio,
+    Socket(String),
+    Listen(String),
+    Pipe(String)
+}
+
+pub type Input = Box<dyn BufRead + Send>;
+pub type Output = Box<dyn Write + Send>;
+
+// Binding to localhost by default, the server shouldn't be reachable from outside unless requested
+fn to_address(value: &str) -> String {
+    if value.parse::<u16>().is_ok() {
+        format!("127.0.0.1:{value}")
+    } else {
+        value.to_string()
+    }
+}
+
+impl Transport {
+    // Picks the transport from the command line arguments, other arguments are ignored
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Transport, String> {
+        let mut transport = Transport::Stdio;
+
+        for arg in args {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg, None)
+            };
+
+            transport = match (name.as_str(), value) {
+                ("--stdio", None) => Transport::Stdio,
+                ("--socket", Some(value)) => Transport::Socket(to_address(&value)),
+                ("--listen", Some(value)) => Transport::Listen(to_address(&value)),
+                ("--pipe", Some(value)) => Transport::Pipe(value),
+                ("--socket" | "--listen", None) => return Err(format!("Missing address for \"{name}\", expected \"{name}=<port>\"!")),
+                ("--pipe", None) => return Err(String::from("Missing path for \"--pipe\", expected \"--pipe=<path>\"!")),
+                _ => continue
+            };
+        }
+
+        Ok(transport)
+    }
+
+    // Establishes the connection to the client
+    pub fn open(&self) -> Result<(Input, Output), String> {
+        match self {
+            Transport::Stdio => Ok((Box::new(BufReader::new(std::io::stdin())), Box::new(std::io::stdout()))),
+            Transport::Socket(address) => {
+                let stream = TcpStream::connect(address).map_err(|error| format!("Error while connecting to {address}: {error}"))?;
+                Transport::from_tcp(stream)
+            },
+            Transport::Listen(address) => {
+                let listener = TcpListener::bind(address).map_err(|error| format!("Error while binding to {address}: {error}"))?;
+                eprintln!("Listening on {address}");
+                let (stream, client) = listener.accept().map_err(|error| format!("Error while accepting connection: {error}"))?;
+                eprintln!("Accepted connection from {client}");
+                Transport::from_tcp(stream)
+            },
+            Transport::Pipe(path) => Transport::open_pipe(path)
+        }
+    }
+
+    #[cfg(unix)]
+    fn open_pipe(path: &str) -> Result<(Input, Output), String> {
+        let stream = std::os::unix::net::UnixStream::connect(path).map_err(|error| format!("Error while connecting to {path}: {error}"))?;
+        let input = stream.try_clone().map_err(|error| error.to_string())?;
+        Ok((Box::new(BufReader::new(input)), Box::new(BufWriter::new(stream))))
+    }
+
+    #[cfg(not(unix))]
+    fn open_pipe(path: &str) -> Result<(Input, Output), String> {
+        Err(format!("Can't connect to {path}, pipes are only supported on Unix!"))
+    }
+
+    fn from_tcp(stream: TcpStream) -> Result<(Input, Output), String> {
+        stream.set_nodelay(true).unwrap_or(()); // messages are flushed one by one, don't wait for more
+        let input = stream.try_clone().map_err(|error| error.to_string())?;
+        Ok((Box::new(BufReader::new(input)), Box::new(BufWriter::new(stream))))
+    }
+}
+
+#[test]
+fn test_from_args() {
+    let args = |args: &[&str]| Transport::from_args(args.iter().map(|arg| arg.to_string()));
+
+    assert_eq!(args(&[]), Ok(Transport::Stdio));
+    assert_eq!(args(&["--stdio", "--clientProcessId=42"]), Ok(Transport::Stdio));
+    assert_eq!(args(&["--socket=5007"]), Ok(Transport::Socket(String::from("127.0.0.1:5007"))));
+    assert_eq!(args(&["--listen=0.0.0.0:5007"]), Ok(Transport::Listen(String::from("0.0.0.0:5007"))));
+    assert_eq!(args(&["--pipe=/tmp/descend.sock"]), Ok(Transport::Pipe(String::from("/tmp/descend.sock"))));
+    assert!(args(&["--socket"]).is_err());
+    assert!(args(&["--pipe"]).is_err());
+}
@@ -0,0 +1,1229 @@
+use std::{collections::HashMap, fmt, ops::Range};
+
+use crate::{ast::{FnDecl, LetStmt, Param, SourceFile, StructDecl}, lexer::TokenKind, resolver::{DefinitionId, DefinitionKind, GenericKind, Resolution}, syntax::{Depth, NodeId, SyntaxKind, SyntaxNode, SyntaxTree}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scalar {
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    Usize,
+    F32,
+    F64
+}
+
+const SCALARS: &[(&str, Scalar)] = &[
+    ("bool", Scalar::Bool), ("i8", Scalar::I8), ("i16", Scalar::I16), ("i32", Scalar::I32), ("i64", Scalar::I64),
+    ("u8", Scalar::U8), ("u16", Scalar::U16), ("u32", Scalar::U32), ("u64", Scalar::U64), ("usize", Scalar::Usize),
+    ("f32", Scalar::F32), ("f64", Scalar::F64)
+];
+
+impl Scalar {
+    fn from_name(name: &str) -> Option<Scalar> {
+        SCALARS.iter().find(|(scalar, _)| *scalar == name).map(|(_, scalar)| *scalar)
+    }
+
+    fn name(self) -> &'static str {
+        SCALARS.iter().find(|(_, scalar)| *scalar == self).map_or("?", |(name, _)| name)
+    }
+
+    fn is_integer(self) -> bool {
+        !matches!(self, Scalar::Bool | Scalar::F32 | Scalar::F64)
+    }
+
+    fn is_float(self) -> bool {
+        matches!(self, Scalar::F32 | Scalar::F64)
+    }
+}
+
+// Sizes of arrays and views, compile-time natural numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Nat {
+    Lit(u64),
+    Ident(String), // a generic parameter of kind nat
+    Binary(char, Box<Nat>, Box<Nat>),
+    Unknown
+}
+
+impl Nat {
+    // Folds the constant parts, e.g. 4*8 to 32
+    fn fold(&self) -> Nat {
+        let Nat::Binary(op, lhs, rhs) = self else {
+            return self.clone();
+        };
+        let (lhs, rhs) = (lhs.fold(), rhs.fold());
+        let folded = match (op, &lhs, &rhs) {
+            ('+', Nat::Lit(a), Nat::Lit(b)) => a.checked_add(*b),
+            ('-', Nat::Lit(a), Nat::Lit(b)) => a.checked_sub(*b),
+            ('*', Nat::Lit(a), Nat::Lit(b)) => a.checked_mul(*b),
+            ('/', Nat::Lit(a), Nat::Lit(b)) => a.checked_div(*b),
+            ('%', Nat::Lit(a), Nat::Lit(b)) => a.checked_rem(*b),
+            _ => None
+        };
+        folded.map_or(Nat::Binary(*op, Box::new(lhs), Box::new(rhs)), Nat::Lit)
+    }
+
+    // Nats with generic parameters could only be compared by solving equations, so they're assumed to fit
+    fn fits(&self, other: &Nat) -> bool {
+        match (self.fold(), other.fold()) {
+            (Nat::Lit(a), Nat::Lit(b)) => a == b,
+            _ => true
+        }
+    }
+
+    fn substitute(&self, substitution: &Substitution) -> Nat {
+        match self {
+            Nat::Ident(name) => match substitution.get(name) {
+                Some(GenericArg::Nat(nat)) => nat.clone(),
+                _ => self.clone()
+            },
+            Nat::Binary(op, lhs, rhs) => Nat::Binary(*op, Box::new(lhs.substitute(substitution)), Box::new(rhs.substitute(substitution))),
+            _ => self.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Memory {
+    CpuMem,
+    GpuGlobal,
+    GpuShared,
+    GpuLocal,
+    Ident(String), // a generic parameter of kind mem
+    Unknown
+}
+
+impl Memory {
+    fn from_token(kind: TokenKind) -> Option<Memory> {
+        Some(match kind {
+            TokenKind::CpuMem => Memory::CpuMem,
+            TokenKind::GpuGlobal => Memory::GpuGlobal,
+            TokenKind::GpuShared => Memory::GpuShared,
+            TokenKind::GpuLocal => Memory::GpuLocal,
+            _ => return None
+        })
+    }
+
+    fn fits(&self, other: &Memory) -> bool {
+        match (self, other) {
+            (Memory::Ident(_) | Memory::Unknown, _) | (_, Memory::Ident(_) | Memory::Unknown) => true,
+            _ => self == other
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ownership {
+    Uniq,
+    Shrd
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provenance {
+    Lifetime(String), // 'a
+    Ident(String), // a generic parameter of kind prv
+    Unknown // inferred by the borrow checker
+}
+
+// Execution resources, scheduling over one yields the next level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exec {
+    CpuThread,
+    GpuGrid,
+    GpuBlock,
+    GpuWarp,
+    GpuThread,
+    Unknown
+}
+
+impl Exec {
+    fn from_token(kind: TokenKind) -> Option<Exec> {
+        Some(match kind {
+            TokenKind::CpuThread => Exec::CpuThread,
+            TokenKind::GpuGrid => Exec::GpuGrid,
+            TokenKind::GpuBlock => Exec::GpuBlock,
+            TokenKind::GpuWarp => Exec::GpuWarp,
+            TokenKind::GpuThread => Exec::GpuThread,
+            _ => return None
+        })
+    }
+
+    fn scheduled(self) -> Exec {
+        match self {
+            Exec::GpuGrid => Exec::GpuBlock,
+            Exec::GpuBlock | Exec::GpuWarp => Exec::GpuThread,
+            _ => Exec::Unknown
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefTy {
+    pub provenance: Provenance,
+    pub ownership: Ownership,
+    pub memory: Memory,
+    pub ty: Ty
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenericArg {
+    Ty(Ty),
+    Nat(Nat)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ty {
+    Scalar(Scalar),
+    Nat, // the value of a nat parameter, like n in n/2
+    Tuple(Vec<Ty>), // () is the unit type
+    Array(Box<Ty>, Nat),
+    View(Box<Ty>, Nat),
+    Ref(Box<RefTy>),
+    At(Box<Ty>, Memory), // T @ gpu.shared
+    Struct(String, Vec<GenericArg>),
+    Generic(String), // a parameter of kind ty or dty
+    Fn(Vec<Ty>, Box<Ty>),
+    Exec(Exec),
+    Unknown // couldn't be inferred, fits everything so errors don't cascade
+}
+
+impl Ty {
+    pub fn unit() -> Ty {
+        Ty::Tuple(Vec::new())
+    }
+
+    // Whether a value of the type can be used where self is expected
+    pub fn fits(&self, found: &Ty) -> bool {
+        match (self, found) {
+            (Ty::Unknown | Ty::Generic(_), _) | (_, Ty::Unknown | Ty::Generic(_)) => true,
+            (Ty::Scalar(a), Ty::Scalar(b)) => a == b,
+            (Ty::Nat, Ty::Scalar(scalar)) | (Ty::Scalar(scalar), Ty::Nat) => scalar.is_integer(),
+            (Ty::Tuple(a), Ty::Tuple(b)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.fits(b)),
+            (Ty::Array(a, n), Ty::Array(b, m)) | (Ty::View(a, n), Ty::View(b, m)) => a.fits(b) && n.fits(m),
+            (Ty::Ref(a), Ty::Ref(b)) => a.ownership == b.ownership && a.memory.fits(&b.memory) && a.ty.fits(&b.ty),
+            (Ty::At(a, m), Ty::At(b, n)) => a.fits(b) && m.fits(n),
+            (Ty::Struct(a, _), Ty::Struct(b, _)) => a == b,
+            (Ty::Fn(a, r), Ty::Fn(b, s)) => a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.fits(b)) && r.fits(s),
+            (Ty::Exec(a), Ty::Exec(b)) => a == b || *a == Exec::Unknown || *b == Exec::Unknown,
+            _ => self == found
+        }
+    }
+
+    fn is_numeric(&self) -> bool {
+        match self {
+            Ty::Scalar(scalar) => *scalar != Scalar::Bool,
+            Ty::Nat | Ty::Unknown | Ty::Generic(_) => true,
+            _ => false
+        }
+    }
+
+    fn is_integer(&self) -> bool {
+        match self {
+            Ty::Scalar(scalar) => scalar.is_integer(),
+            Ty::Nat | Ty::Unknown | Ty::Generic(_) => true,
+            _ => false
+        }
+    }
+
+    // Arrays, views and the data they are placed in memory with
+    fn element(&self) -> Option<&Ty> {
+        match self {
+            Ty::Array(element, _) | Ty::View(element, _) => Some(element),
+            Ty::At(ty, _) => ty.element(),
+            _ => None
+        }
+    }
+
+    fn substitute(&self, substitution: &Substitution) -> Ty {
+        match self {
+            Ty::Generic(name) => match substitution.get(name) {
+                Some(GenericArg::Ty(ty)) => ty.clone(),
+                _ => self.clone()
+            },
+            Ty::Tuple(elements) => Ty::Tuple(elements.iter().map(|element| element.substitute(substitution)).collect()),
+            Ty::Array(element, n) => Ty::Array(Box::new(element.substitute(substitution)), n.substitute(substitution)),
+            Ty::View(element, n) => Ty::View(Box::new(element.substitute(substitution)), n.substitute(substitution)),
+            Ty::Ref(reference) => Ty::Ref(Box::new(RefTy { ty: reference.ty.substitute(substitution), ..(**reference).clone() })),
+            Ty::At(ty, memory) => Ty::At(Box::new(ty.substitute(substitution)), memory.clone()),
+            _ => self.clone()
+        }
+    }
+}
+
+type Substitution = HashMap<String, GenericArg>;
+
+impl fmt::Display for Nat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Nat::Lit(n) => write!(f, "{n}"),
+            Nat::Ident(name) => write!(f, "{name}"),
+            Nat::Binary(op, lhs, rhs) => {
+                let operand = |nat: &Nat| match nat {
+                    Nat::Binary(..) => format!("({nat})"),
+                    _ => nat.to_string()
+                };
+                write!(f, "{}{op}{}", operand(lhs), operand(rhs))
+            },
+            Nat::Unknown => write!(f, "_")
+        }
+    }
+}
+
+impl fmt::Display for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Memory::CpuMem => write!(f, "cpu.mem"),
+            Memory::GpuGlobal => write!(f, "gpu.global"),
+            Memory::GpuShared => write!(f, "gpu.shared"),
+            Memory::GpuLocal => write!(f, "gpu.local"),
+            Memory::Ident(name) => write!(f, "{name}"),
+            Memory::Unknown => write!(f, "_")
+        }
+    }
+}
+
+impl fmt::Display for Exec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exec::CpuThread => write!(f, "cpu.thread"),
+            Exec::GpuGrid => write!(f, "gpu.grid"),
+            Exec::GpuBlock => write!(f, "gpu.block"),
+            Exec::GpuWarp => write!(f, "gpu.warp"),
+            Exec::GpuThread => write!(f, "gpu.thread"),
+            Exec::Unknown => write!(f, "_")
+        }
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |tys: &[Ty]| tys.iter().map(Ty::to_string).collect::<Vec<String>>().join(", ");
+        match self {
+            Ty::Scalar(scalar) => write!(f, "{}", scalar.name()),
+            Ty::Nat => write!(f, "nat"),
+            Ty::Tuple(elements) if elements.len() == 1 => write!(f, "({},)", elements[0]),
+            Ty::Tuple(elements) => write!(f, "({})", list(elements)),
+            Ty::Array(element, n) => write!(f, "[{element}; {n}]"),
+            Ty::View(element, n) => write!(f, "[[{element}; {n}]]"),
+            Ty::Ref(reference) => {
+                write!(f, "&")?;
+                match &reference.provenance {
+                    Provenance::Lifetime(name) | Provenance::Ident(name) => write!(f, "{name} ")?,
+                    Provenance::Unknown => {}
+                }
+                write!(f, "{}", if reference.ownership == Ownership::Uniq { "uniq" } else { "shrd" })?;
+                if reference.memory != Memory::Unknown {
+                    write!(f, " {}", reference.memory)?;
+                }
+                write!(f, " {}", reference.ty)
+            },
+            Ty::At(ty, memory) => write!(f, "{ty} @ {memory}"),
+            Ty::Struct(name, args) if args.is_empty() => write!(f, "{name}"),
+            Ty::Struct(name, args) => {
+                let args: Vec<String> = args.iter().map(|arg| match arg {
+                    GenericArg::Ty(ty) => ty.to_string(),
+                    GenericArg::Nat(nat) => nat.to_string()
+                }).collect();
+                write!(f, "{name}<{}>", args.join(", "))
+            },
+            Ty::Generic(name) => write!(f, "{name}"),
+            Ty::Fn(params, ret) => write!(f, "fn({}) -> {ret}", list(params)),
+            Ty::Exec(exec) => write!(f, "{exec}"),
+            Ty::Unknown => write!(f, "_")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeError {
+    pub span: Range<usize>,
+    pub message: String,
+    pub code: &'static str, // "type-mismatch", "invalid-operation", "unknown-field", "not-callable", "wrong-argument-count" or "nested-too-deeply"
+    pub related: Option<(Range<usize>, String)> // the declaration that led to the expected type
+}
+
+// Types of the expressions and definitions of a syntax tree
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Types {
+    pub expressions: HashMap<NodeId, Ty>,
+    pub definitions: HashMap<DefinitionId, Ty>,
+    pub errors: Vec<TypeError>
+}
+
+impl Types {
+    // Describes what's at the offset for hovers: the signature of an item, the type of a variable or
+    // of the innermost expression. Returns the span that's described as well.
+    pub fn describe_at(&self, tree: &SyntaxTree, resolution: &Resolution, offset: usize) -> Option<(Range<usize>, String)> {
+        let token = tree.root.token_at(offset)?;
+        if let Some(id) = resolution.definition_at(tree, offset) {
+            let definition = resolution.definition(id);
+            let declaration = tree.root.ancestors_at(definition.span.start).into_iter().rev().nth(1)?;
+            let description = match definition.kind {
+                DefinitionKind::Function | DefinitionKind::Struct => signature(tree, declaration),
+                DefinitionKind::Generic(_) => collapse(tree.text(&declaration.span)),
+                _ => match self.definitions.get(&id) {
+                    Some(ty) => format!("{}: {ty}", definition.name),
+                    None => definition.name.clone()
+                }
+            };
+            return Some((token.span.clone(), description));
+        }
+        let ancestors = tree.root.ancestors_at(token.span.start);
+        let (node, ty) = ancestors.iter().rev().find_map(|node| Some((node, self.expressions.get(&node.id)?)))?;
+        Some((node.span.clone(), ty.to_string()))
+    }
+}
+
+fn collapse(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// The declaration of a function or struct up to its body
+fn signature(tree: &SyntaxTree, declaration: &SyntaxNode) -> String {
+    let end = match declaration.kind {
+        SyntaxKind::FnDecl => declaration.node(SyntaxKind::Block).map(|block| block.span.start),
+        _ => declaration.token(TokenKind::LBrace).map(|brace| brace.span.start)
+    };
+    collapse(tree.text(&(declaration.span.start..end.unwrap_or(declaration.span.end))))
+}
+
+struct StructInfo {
+    generics: Vec<String>,
+    fields: Vec<(String, Ty)>
+}
+
+struct Checker<'t> {
+    tree: &'t SyntaxTree,
+    resolution: &'t Resolution,
+    types: Types,
+    structs: HashMap<String, StructInfo>,
+    params: HashMap<DefinitionId, Vec<Range<usize>>>, // declarations of the parameters of each function
+    depth: Depth, // of the expressions being checked
+    too_deep: bool // whether the current function was reported to be nested too deeply
+}
+
+// A suffix like in 1u32 or 1.0f32 gives the type of a literal
+fn suffix(literal: &str) -> Option<Scalar> {
+    SCALARS.iter().find(|(name, _)| literal.len() > name.len() && literal.ends_with(name)).map(|(_, scalar)| *scalar)
+}
+
+impl<'t> Checker<'t> {
+    fn text(&self, node: &SyntaxNode) -> &'t str {
+        self.tree.text(&node.span)
+    }
+
+    fn error(&mut self, span: Range<usize>, code: &'static str, message: String, related: Option<(Range<usize>, String)>) {
+        self.types.errors.push(TypeError { span, message, code, related });
+    }
+
+    fn mismatch(&mut self, node: &SyntaxNode, expected: &Ty, found: &Ty, related: Option<(Range<usize>, String)>) {
+        self.error(node.span.clone(), "type-mismatch", format!("Mismatched types: expected `{expected}`, found `{found}`"), related);
+    }
+
+    fn definition_type(&mut self, name: Option<&SyntaxNode>, ty: Ty) {
+        if let Some(definition) = name.and_then(|name| self.resolution.definitions_by_node.get(&name.id)) {
+            self.types.definitions.insert(*definition, ty);
+        }
+    }
+
+    fn referenced(&self, name_ref: &SyntaxNode) -> Option<(DefinitionId, DefinitionKind)> {
+        let definition = *self.resolution.references.get(&name_ref.id)?;
+        Some((definition, self.resolution.definition(definition).kind))
+    }
+
+    // Types
+
+    fn lower(&self, node: &SyntaxNode) -> Ty {
+        match node.kind {
+            SyntaxKind::PathType => {
+                let Some(name_ref) = node.node(SyntaxKind::NameRef) else {
+                    return Ty::Unknown;
+                };
+                let name = self.text(name_ref).trim();
+                match self.referenced(name_ref) {
+                    Some((_, DefinitionKind::Struct)) => {
+                        let args = node.node(SyntaxKind::GenericArgList).map_or(Vec::new(), |args| args.nodes().map(|arg| self.lower_generic_arg(arg)).collect());
+                        Ty::Struct(name.to_string(), args)
+                    },
+                    Some((_, DefinitionKind::Generic(_))) => Ty::Generic(name.to_string()),
+                    _ => Scalar::from_name(name).map_or(Ty::Unknown, Ty::Scalar)
+                }
+            },
+            SyntaxKind::RefType => {
+                let provenance = match (node.token(TokenKind::Lifetime), node.nodes().find(|child| child.kind == SyntaxKind::NameRef)) {
+                    (Some(lifetime), _) => Provenance::Lifetime(self.tree.text(&lifetime.span).to_string()),
+                    (None, Some(name_ref)) if node.token(TokenKind::Uniq).or(node.token(TokenKind::Shrd)).is_some_and(|token| name_ref.span.start < token.span.start) => {
+                        Provenance::Ident(self.text(name_ref).trim().to_string())
+                    },
+                    _ => Provenance::Unknown
+                };
+                let ownership = if node.token(TokenKind::Shrd).is_some() { Ownership::Shrd } else { Ownership::Uniq };
+                let ty = node.nodes().filter(|child| child.kind != SyntaxKind::NameRef).last().map_or(Ty::Unknown, |ty| self.lower(ty));
+                Ty::Ref(Box::new(RefTy { provenance, ownership, memory: self.memory(node), ty }))
+            },
+            SyntaxKind::ArrayType | SyntaxKind::ViewType => {
+                let mut children = node.nodes();
+                let element = children.next().map_or(Ty::Unknown, |element| self.lower(element));
+                let n = children.next().map_or(Nat::Unknown, |n| self.lower_nat(n));
+                if node.kind == SyntaxKind::ArrayType {
+                    Ty::Array(Box::new(element), n)
+                } else {
+                    Ty::View(Box::new(element), n)
+                }
+            },
+            SyntaxKind::TupleType => Ty::Tuple(node.nodes().map(|element| self.lower(element)).collect()),
+            SyntaxKind::AtType => {
+                let ty = node.nodes().next().map_or(Ty::Unknown, |ty| self.lower(ty));
+                Ty::At(Box::new(ty), self.memory(node))
+            },
+            _ => Ty::Unknown
+        }
+    }
+
+    // The memory of a reference or at type, given by a token like gpu.global or a generic parameter
+    fn memory(&self, node: &SyntaxNode) -> Memory {
+        if let Some(memory) = node.tokens().find_map(|token| Memory::from_token(token.kind)) {
+            return memory;
+        }
+        let ownership = node.tokens().find(|token| matches!(token.kind, TokenKind::Uniq | TokenKind::Shrd | TokenKind::At));
+        let name_ref = node.nodes().find(|child| child.kind == SyntaxKind::NameRef && ownership.is_some_and(|token| child.span.start > token.span.start));
+        name_ref.map_or(Memory::Unknown, |name_ref| Memory::Ident(self.text(name_ref).trim().to_string()))
+    }
+
+    fn lower_nat(&self, node: &SyntaxNode) -> Nat {
+        match node.kind {
+            SyntaxKind::Literal => self.text(node).trim().replace('_', "").parse().map_or(Nat::Unknown, Nat::Lit),
+            SyntaxKind::PathExpr => node.node(SyntaxKind::NameRef).map_or(Nat::Unknown, |name_ref| Nat::Ident(self.text(name_ref).trim().to_string())),
+            SyntaxKind::ParenExpr => node.nodes().next().map_or(Nat::Unknown, |inner| self.lower_nat(inner)),
+            SyntaxKind::BinaryExpr => {
+                let op = node.tokens().next().and_then(|token| match token.kind {
+                    TokenKind::Plus => Some('+'),
+                    TokenKind::Minus => Some('-'),
+                    TokenKind::Star => Some('*'),
+                    TokenKind::Slash => Some('/'),
+                    TokenKind::Percent => Some('%'),
+                    _ => None
+                });
+                let mut operands = node.nodes();
+                match (op, operands.next(), operands.next()) {
+                    (Some(op), Some(lhs), Some(rhs)) => Nat::Binary(op, Box::new(self.lower_nat(lhs)), Box::new(self.lower_nat(rhs))),
+                    _ => Nat::Unknown
+                }
+            },
+            _ => Nat::Unknown
+        }
+    }
+
+    // Generic arguments are types or nats, a lone name is a type if it refers to one
+    fn lower_generic_arg(&self, node: &SyntaxNode) -> GenericArg {
+        let name_ref = node.node(SyntaxKind::NameRef).filter(|_| node.kind == SyntaxKind::PathExpr);
+        let is_type = match name_ref {
+            Some(name_ref) => match self.referenced(name_ref) {
+                Some((_, DefinitionKind::Generic(Some(GenericKind::Nat)))) => false,
+                Some((_, DefinitionKind::Generic(_) | DefinitionKind::Struct)) => true,
+                _ => Scalar::from_name(self.text(name_ref).trim()).is_some()
+            },
+            None => matches!(node.kind, SyntaxKind::PathType | SyntaxKind::RefType | SyntaxKind::ArrayType | SyntaxKind::ViewType | SyntaxKind::TupleType | SyntaxKind::AtType)
+        };
+        match (is_type, name_ref) {
+            (true, Some(name_ref)) => {
+                let name = self.text(name_ref).trim();
+                GenericArg::Ty(Scalar::from_name(name).map_or_else(|| Ty::Generic(name.to_string()), Ty::Scalar))
+            },
+            (true, None) => GenericArg::Ty(self.lower(node)),
+            (false, _) => GenericArg::Nat(self.lower_nat(node))
+        }
+    }
+
+    // Items
+
+    // Signatures first, so items can be used before they are declared
+    fn declarations(&mut self, file: SourceFile) {
+        for declaration in file.structs() {
+            self.struct_decl(declaration);
+        }
+        for function in file.functions() {
+            for param in function.generic_params() {
+                if param.kind() == Some(TokenKind::Nat) {
+                    self.definition_type(param.name().map(|name| name.syntax()), Ty::Nat);
+                }
+            }
+            if let Some(exec) = function.exec() {
+                let exec_ty = exec.exec_type().and_then(|exec_type| exec_type.tokens().find_map(|token| Exec::from_token(token.kind)));
+                self.definition_type(exec.name().map(|name| name.syntax()), Ty::Exec(exec_ty.unwrap_or(Exec::Unknown)));
+            }
+            let params: Vec<Ty> = function.params().map(|param| self.param(param)).collect();
+            let ret = function.ret_type().map_or_else(Ty::unit, |ret| self.lower(ret));
+            let name = function.name().map(|name| name.syntax());
+            if let Some(definition) = name.and_then(|name| self.resolution.definitions_by_node.get(&name.id)) {
+                self.params.insert(*definition, function.params().map(|param| param.syntax().span.clone()).collect());
+            }
+            self.definition_type(name, Ty::Fn(params, Box::new(ret)));
+        }
+    }
+
+    fn struct_decl(&mut self, declaration: StructDecl) {
+        let Some(name) = declaration.name() else {
+            return;
+        };
+        let generics = declaration.generic_params().filter_map(|param| param.name()).map(|name| self.text(name.syntax()).trim().to_string()).collect();
+        let mut fields = Vec::new();
+        for field in declaration.fields() {
+            let ty = field.ty().map_or(Ty::Unknown, |ty| self.lower(ty));
+            self.definition_type(field.name().map(|name| name.syntax()), ty.clone());
+            if let Some(name) = field.name() {
+                fields.push((self.text(name.syntax()).trim().to_string(), ty));
+            }
+        }
+        let name = self.text(name.syntax()).trim().to_string();
+        self.structs.entry(name).or_insert(StructInfo { generics, fields });
+    }
+
+    fn param(&mut self, param: Param) -> Ty {
+        let ty = param.ty().map_or(Ty::Unknown, |ty| self.lower(ty));
+        self.definition_type(param.name().map(|name| name.syntax()), ty.clone());
+        ty
+    }
+
+    fn fn_decl(&mut self, function: FnDecl) {
+        let (Some(body), ret_type) = (function.body(), function.ret_type()) else {
+            return;
+        };
+        let ret = ret_type.map_or_else(Ty::unit, |ret| self.lower(ret));
+        let related = ret_type.map(|ret_type| (ret_type.span.clone(), String::from("Return type declared here")));
+        self.too_deep = false;
+        self.block(body.syntax(), Some(&ret), related);
+    }
+
+    // Statements
+
+    // Checks the statements, the value of the block against the expected type if there is one
+    fn block(&mut self, block: &SyntaxNode, expected: Option<&Ty>, related: Option<(Range<usize>, String)>) -> Ty {
+        let mut value = None;
+        for statement in block.nodes() {
+            match statement.kind {
+                SyntaxKind::LetStmt => self.let_stmt(statement),
+                SyntaxKind::ExprStmt => {
+                    if let Some(expression) = statement.nodes().next() {
+                        self.infer(expression);
+                    }
+                },
+                SyntaxKind::Error => {},
+                _ => value = Some(statement)
+            }
+        }
+        match (value, expected) {
+            (Some(value), Some(expected)) => self.check(value, expected, related),
+            (Some(value), None) => self.infer(value),
+            (None, Some(expected)) => {
+                if !expected.fits(&Ty::unit()) {
+                    // the missing value is reported at the end of the block
+                    let end = block.all_tokens().into_iter().rev().find(|token| !token.kind.is_trivia()).map_or(block.span.clone(), |token| token.span.clone());
+                    self.error(end, "type-mismatch", format!("Mismatched types: expected `{expected}`, found `()`"), related);
+                }
+                Ty::unit()
+            },
+            (None, None) => Ty::unit()
+        }
+    }
+
+    fn let_stmt(&mut self, node: &SyntaxNode) {
+        let Some(statement) = LetStmt::cast(node) else {
+            return;
+        };
+        let declared = statement.ty().map(|ty| (ty, self.lower(ty)));
+        let ty = match (declared, statement.initializer()) {
+            (Some((ty_node, declared)), Some(initializer)) => {
+                self.check(initializer, &declared, Some((ty_node.span.clone(), String::from("Type declared here"))));
+                declared
+            },
+            (Some((_, declared)), None) => declared,
+            (None, Some(initializer)) => self.infer(initializer),
+            (None, None) => Ty::Unknown
+        };
+        self.definition_type(statement.name().map(|name| name.syntax()), ty);
+    }
+
+    // Expressions
+
+    // Infers the type of an expression and checks that it fits the expected one. Literals take the
+    // expected type, like 1 in "let x: u32 = 1;".
+    fn check(&mut self, node: &SyntaxNode, expected: &Ty, related: Option<(Range<usize>, String)>) -> Ty {
+        if !self.depth.enter() {
+            return self.too_deep(node);
+        }
+        let ty = match (node.kind, expected) {
+            (SyntaxKind::Literal, Ty::Scalar(scalar)) => {
+                let literal = self.literal(node);
+                let unsuffixed = suffix(self.text(node).trim()).is_none();
+                let fits = matches!(&literal, Ty::Scalar(found) if unsuffixed && ((found.is_integer() && scalar.is_integer()) || (found.is_float() && scalar.is_float())));
+                if fits { expected.clone() } else { literal }
+            },
+            (SyntaxKind::ParenExpr, _) => match node.nodes().next() {
+                Some(inner) => self.check(inner, expected, related.clone()),
+                None => Ty::Unknown
+            },
+            _ => self.infer(node)
+        };
+        self.depth.exit();
+        if !expected.fits(&ty) {
+            self.mismatch(node, expected, &ty, related);
+        }
+        self.types.expressions.insert(node.id, ty.clone());
+        ty
+    }
+
+    fn infer(&mut self, node: &SyntaxNode) -> Ty {
+        if !self.depth.enter() {
+            return self.too_deep(node);
+        }
+        let ty = self.expression(node);
+        self.depth.exit();
+        self.types.expressions.insert(node.id, ty.clone());
+        ty
+    }
+
+    // Expressions nested deeper than that aren't checked, they would overflow the stack. It's only
+    // reported once per function, at the first one.
+    fn too_deep(&mut self, node: &SyntaxNode) -> Ty {
+        if !self.too_deep {
+            self.too_deep = true;
+            self.error(node.span.clone(), "nested-too-deeply", String::from("Expression is nested too deeply to be checked"), None);
+        }
+        Ty::Unknown
+    }
+
+    fn literal(&self, node: &SyntaxNode) -> Ty {
+        let Some(token) = node.tokens().next() else {
+            return Ty::Unknown;
+        };
+        match (token.kind, suffix(self.tree.text(&token.span))) {
+            (TokenKind::Integer | TokenKind::Float, Some(scalar)) => Ty::Scalar(scalar),
+            (TokenKind::Integer, None) => Ty::Scalar(Scalar::I32),
+            (TokenKind::Float, None) => Ty::Scalar(Scalar::F64),
+            (TokenKind::True | TokenKind::False, _) => Ty::Scalar(Scalar::Bool),
+            _ => Ty::Unknown
+        }
+    }
+
+    fn expression(&mut self, node: &SyntaxNode) -> Ty {
+        let mut children = node.nodes();
+        match node.kind {
+            SyntaxKind::Literal => self.literal(node),
+            SyntaxKind::PathExpr => self.path_expr(node),
+            SyntaxKind::ParenExpr => children.next().map_or(Ty::Unknown, |inner| self.infer(inner)),
+            SyntaxKind::TupleExpr => Ty::Tuple(children.map(|element| self.infer(element)).collect()),
+            SyntaxKind::ArrayExpr => {
+                let elements: Vec<&SyntaxNode> = children.filter(|child| child.kind != SyntaxKind::Error).collect();
+                let Some(first) = elements.first() else {
+                    return Ty::Array(Box::new(Ty::Unknown), Nat::Lit(0));
+                };
+                let element = self.infer(first);
+                for other in &elements[1..] {
+                    self.check(other, &element, None);
+                }
+                Ty::Array(Box::new(element), Nat::Lit(elements.len() as u64))
+            },
+            SyntaxKind::BinaryExpr => self.binary_expr(node),
+            SyntaxKind::PrefixExpr => self.prefix_expr(node),
+            SyntaxKind::BorrowExpr => {
+                let Some(place) = children.next() else {
+                    return Ty::Unknown;
+                };
+                let ty = self.infer(place);
+                let provenance = node.token(TokenKind::Lifetime).map_or(Provenance::Unknown, |lifetime| Provenance::Lifetime(self.tree.text(&lifetime.span).to_string()));
+                let ownership = if node.token(TokenKind::Shrd).is_some() { Ownership::Shrd } else { Ownership::Uniq };
+                Ty::Ref(Box::new(RefTy { provenance, ownership, memory: self.place_memory(place), ty }))
+            },
+            SyntaxKind::CallExpr => {
+                let (Some(callee), args) = (children.next(), children.next()) else {
+                    return Ty::Unknown;
+                };
+                let callee_ty = self.infer(callee);
+                self.call(callee, callee_ty, args)
+            },
+            SyntaxKind::KernelLaunch => {
+                let Some(callee) = children.next() else {
+                    return Ty::Unknown;
+                };
+                let callee_ty = self.infer(callee);
+                let args = node.node(SyntaxKind::ArgList);
+                self.call(callee, callee_ty, args);
+                Ty::unit()
+            },
+            SyntaxKind::IndexExpr => {
+                let (Some(base), index) = (children.next(), children.next()) else {
+                    return Ty::Unknown;
+                };
+                let base_ty = self.infer(base);
+                if let Some(index) = index {
+                    let index_ty = self.infer(index);
+                    if !index_ty.is_integer() {
+                        self.error(index.span.clone(), "type-mismatch", format!("Index must be an integer, found `{index_ty}`"), None);
+                    }
+                }
+                self.element(node, &base_ty, "index into")
+            },
+            SyntaxKind::SelectExpr => {
+                let (Some(base), resource) = (children.next(), children.next()) else {
+                    return Ty::Unknown;
+                };
+                let base_ty = self.infer(base);
+                if let Some(resource) = resource {
+                    self.infer(resource);
+                }
+                self.element(node, &base_ty, "select from")
+            },
+            SyntaxKind::FieldExpr => self.field_expr(node),
+            SyntaxKind::ViewExpr => self.view_expr(node),
+            SyntaxKind::ClosureExpr => self.closure_expr(node),
+            SyntaxKind::IfExpr => self.if_expr(node),
+            SyntaxKind::WhileExpr => {
+                for child in children {
+                    match child.kind {
+                        SyntaxKind::Block => {
+                            self.block(child, None, None);
+                        },
+                        _ => {
+                            self.check(child, &Ty::Scalar(Scalar::Bool), None);
+                        }
+                    }
+                }
+                Ty::unit()
+            },
+            SyntaxKind::ForExpr => self.for_expr(node),
+            SyntaxKind::SchedExpr => {
+                let mut binder = Ty::Unknown;
+                for child in node.nodes() {
+                    match child.kind {
+                        SyntaxKind::Name | SyntaxKind::NameRef => {},
+                        SyntaxKind::Block => {
+                            self.definition_type(node.node(SyntaxKind::Name), binder.clone());
+                            self.block(child, None, None);
+                        },
+                        _ => {
+                            binder = match self.infer(child) {
+                                Ty::Exec(exec) => Ty::Exec(exec.scheduled()),
+                                Ty::Unknown => Ty::Unknown,
+                                ty => {
+                                    self.error(child.span.clone(), "type-mismatch", format!("Expected an execution resource to schedule over, found `{ty}`"), None);
+                                    Ty::Unknown
+                                }
+                            };
+                        }
+                    }
+                }
+                Ty::unit()
+            },
+            SyntaxKind::SplitExpr => {
+                // split(X) position resource { arms }
+                let operands: Vec<&SyntaxNode> = children.filter(|child| !matches!(child.kind, SyntaxKind::NameRef | SyntaxKind::SplitArm | SyntaxKind::Error)).collect();
+                if let Some(position) = operands.first() {
+                    let ty = self.infer(position);
+                    if !ty.is_integer() {
+                        self.error(position.span.clone(), "type-mismatch", format!("Expected the position to split at, found `{ty}`"), None);
+                    }
+                }
+                let resource = operands.get(1).map_or(Ty::Unknown, |resource| self.infer(resource));
+                for arm in node.nodes().filter(|child| child.kind == SyntaxKind::SplitArm) {
+                    self.definition_type(arm.node(SyntaxKind::Name), resource.clone());
+                    if let Some(block) = arm.node(SyntaxKind::Block) {
+                        self.block(block, None, None);
+                    }
+                }
+                Ty::unit()
+            },
+            SyntaxKind::SyncExpr => {
+                for arg in node.node(SyntaxKind::ArgList).into_iter().flat_map(SyntaxNode::nodes) {
+                    self.infer(arg);
+                }
+                Ty::unit()
+            },
+            SyntaxKind::UnsafeExpr | SyntaxKind::BlockExpr => node.node(SyntaxKind::Block).map_or(Ty::Unknown, |block| self.block(block, None, None)),
+            _ => Ty::Unknown
+        }
+    }
+
+    fn path_expr(&mut self, node: &SyntaxNode) -> Ty {
+        if let Some(exec) = node.tokens().find_map(|token| Exec::from_token(token.kind)) {
+            return Ty::Exec(exec);
+        }
+        let Some((definition, _)) = node.node(SyntaxKind::NameRef).and_then(|name_ref| self.referenced(name_ref)) else {
+            return Ty::Unknown; // builtins and unresolved names
+        };
+        self.types.definitions.get(&definition).cloned().unwrap_or(Ty::Unknown)
+    }
+
+    // The memory a place is in, if it's known from a reference it's reached through
+    fn place_memory(&self, place: &SyntaxNode) -> Memory {
+        match place.kind {
+            SyntaxKind::PrefixExpr if place.token(TokenKind::Star).is_some() => {
+                match place.nodes().next().and_then(|operand| self.types.expressions.get(&operand.id)) {
+                    Some(Ty::Ref(reference)) => reference.memory.clone(),
+                    _ => Memory::Unknown
+                }
+            },
+            SyntaxKind::ParenExpr | SyntaxKind::IndexExpr | SyntaxKind::SelectExpr | SyntaxKind::FieldExpr | SyntaxKind::ViewExpr => {
+                place.nodes().next().map_or(Memory::Unknown, |inner| self.place_memory(inner))
+            },
+            SyntaxKind::PathExpr => match self.types.expressions.get(&place.id) {
+                Some(Ty::At(_, memory)) => memory.clone(),
+                _ => Memory::Unknown
+            },
+            _ => Memory::Unknown
+        }
+    }
+
+    fn element(&mut self, node: &SyntaxNode, base: &Ty, operation: &str) -> Ty {
+        match base.element() {
+            Some(element) => element.clone(),
+            None if matches!(base, Ty::Unknown | Ty::Generic(_)) => Ty::Unknown,
+            None => {
+                self.error(node.span.clone(), "invalid-operation", format!("Cannot {operation} a value of type `{base}`"), None);
+                Ty::Unknown
+            }
+        }
+    }
+
+    fn call(&mut self, callee: &SyntaxNode, callee_ty: Ty, args: Option<&SyntaxNode>) -> Ty {
+        let arg_nodes: Vec<&SyntaxNode> = args.into_iter().flat_map(SyntaxNode::nodes).filter(|arg| arg.kind != SyntaxKind::Error).collect();
+        let Ty::Fn(params, ret) = callee_ty else {
+            for arg in &arg_nodes {
+                self.infer(arg);
+            }
+            if !matches!(callee_ty, Ty::Unknown | Ty::Generic(_)) {
+                self.error(callee.span.clone(), "not-callable", format!("Expected a function, found `{callee_ty}`"), None);
+            }
+            return Ty::Unknown;
+        };
+
+        // the declarations of the parameters, if the callee is a function of this file
+        let definition = callee.node(SyntaxKind::NameRef).and_then(|name_ref| self.referenced(name_ref)).map(|(definition, _)| definition);
+        let declarations = definition.and_then(|definition| self.params.get(&definition)).cloned().unwrap_or_default();
+        if arg_nodes.len() != params.len() {
+            let span = args.map_or(callee.span.clone(), |args| args.span.clone());
+            let plural = if params.len() == 1 { "" } else { "s" };
+            self.error(span, "wrong-argument-count", format!("Expected {} argument{plural}, found {}", params.len(), arg_nodes.len()), None);
+        }
+        for (i, arg) in arg_nodes.iter().enumerate() {
+            match params.get(i) {
+                Some(param) => {
+                    let related = declarations.get(i).map(|span| (span.clone(), String::from("Parameter declared here")));
+                    self.check(arg, param, related);
+                },
+                None => {
+                    self.infer(arg);
+                }
+            }
+        }
+        *ret
+    }
+
+    fn binary_expr(&mut self, node: &SyntaxNode) -> Ty {
+        let mut operands = node.nodes();
+        let (Some(lhs), rhs, Some(op)) = (operands.next(), operands.next(), node.tokens().next()) else {
+            return Ty::Unknown;
+        };
+        let lhs_ty = self.infer(lhs);
+        let op_text = self.tree.text(&op.span);
+        let rhs = |checker: &mut Self, expected: &Ty| rhs.map(|rhs| checker.check(rhs, expected, None));
+        match op.kind {
+            TokenKind::Eq => {
+                rhs(self, &lhs_ty);
+                Ty::unit()
+            },
+            TokenKind::PlusEq | TokenKind::MinusEq | TokenKind::StarEq | TokenKind::SlashEq => {
+                if !lhs_ty.is_numeric() {
+                    self.error(node.span.clone(), "invalid-operation", format!("Cannot apply `{op_text}` to `{lhs_ty}`"), None);
+                }
+                rhs(self, &lhs_ty);
+                Ty::unit()
+            },
+            TokenKind::AmpAmp | TokenKind::PipePipe => {
+                let bool_ty = Ty::Scalar(Scalar::Bool);
+                if !bool_ty.fits(&lhs_ty) {
+                    self.mismatch(lhs, &bool_ty, &lhs_ty, None);
+                }
+                rhs(self, &bool_ty);
+                bool_ty
+            },
+            TokenKind::EqEq | TokenKind::Ne | TokenKind::Lt | TokenKind::Le | TokenKind::Gt | TokenKind::Ge => {
+                rhs(self, &lhs_ty);
+                Ty::Scalar(Scalar::Bool)
+            },
+            TokenKind::DotDot => {
+                rhs(self, &lhs_ty);
+                Ty::Unknown // ranges only appear in for loops, which take the type of the bounds
+            },
+            _ => {
+                if !lhs_ty.is_numeric() {
+                    self.error(node.span.clone(), "invalid-operation", format!("Cannot apply `{op_text}` to `{lhs_ty}`"), None);
+                    rhs(self, &Ty::Unknown);
+                    return Ty::Unknown;
+                }
+                rhs(self, &lhs_ty);
+                lhs_ty
+            }
+        }
+    }
+
+    fn prefix_expr(&mut self, node: &SyntaxNode) -> Ty {
+        let (Some(operand), Some(op)) = (node.nodes().next(), node.tokens().next()) else {
+            return Ty::Unknown;
+        };
+        let ty = self.infer(operand);
+        match op.kind {
+            TokenKind::Star => match ty {
+                Ty::Ref(reference) => reference.ty,
+                Ty::Unknown | Ty::Generic(_) => Ty::Unknown,
+                ty => {
+                    self.error(node.span.clone(), "invalid-operation", format!("Cannot dereference a value of type `{ty}`"), None);
+                    Ty::Unknown
+                }
+            },
+            TokenKind::Bang if ty.is_integer() || ty == Ty::Scalar(Scalar::Bool) => ty,
+            TokenKind::Minus if ty.is_numeric() => ty,
+            _ => {
+                self.error(node.span.clone(), "invalid-operation", format!("Cannot apply `{}` to `{ty}`", self.tree.text(&op.span)), None);
+                Ty::Unknown
+            }
+        }
+    }
+
+    fn field_expr(&mut self, node: &SyntaxNode) -> Ty {
+        let Some(base) = node.nodes().next() else {
+            return Ty::Unknown;
+        };
+        let base_ty = self.infer(base);
+        let field = node.node(SyntaxKind::NameRef).map(|name_ref| self.text(name_ref).trim())
+            .or_else(|| node.token(TokenKind::Integer).map(|index| self.tree.text(&index.span)));
+        let Some(field) = field else {
+            return Ty::Unknown;
+        };
+        let unwrapped = match &base_ty {
+            Ty::At(ty, _) => ty.as_ref(),
+            ty => ty
+        };
+        let found = match unwrapped {
+            Ty::Struct(name, args) => {
+                let Some(info) = self.structs.get(name) else {
+                    return Ty::Unknown;
+                };
+                let substitution: Substitution = info.generics.iter().cloned().zip(args.iter().cloned()).collect();
+                info.fields.iter().find(|(name, _)| name == field).map(|(_, ty)| ty.substitute(&substitution))
+            },
+            Ty::Tuple(elements) => field.parse::<usize>().ok().and_then(|index| elements.get(index)).cloned(),
+            // views without arguments
+            Ty::Array(element, n) | Ty::View(element, n) if field == "to_view" => Some(Ty::View(element.clone(), n.clone())),
+            Ty::Array(..) | Ty::View(..) | Ty::Unknown | Ty::Generic(_) => Some(Ty::Unknown),
+            _ => None
+        };
+        found.unwrap_or_else(|| {
+            self.error(node.span.clone(), "unknown-field", format!("No field `{field}` on type `{base_ty}`"), None);
+            Ty::Unknown
+        })
+    }
+
+    // x.grp::<k> groups the elements of a view, x.split_at::<k> splits it in two
+    fn view_expr(&mut self, node: &SyntaxNode) -> Ty {
+        let Some(base) = node.nodes().next() else {
+            return Ty::Unknown;
+        };
+        let base_ty = self.infer(base);
+        let name = node.node(SyntaxKind::NameRef).map(|name_ref| self.text(name_ref).trim());
+        let k = node.node(SyntaxKind::GenericArgList).and_then(|args| args.nodes().next()).map_or(Nat::Unknown, |k| self.lower_nat(k));
+        let (Some(element), Ty::Array(_, n) | Ty::View(_, n)) = (base_ty.element(), &base_ty) else {
+            return Ty::Unknown;
+        };
+        match name {
+            Some("grp") => Ty::View(Box::new(Ty::View(Box::new(element.clone()), k.clone())), Nat::Binary('/', Box::new(n.clone()), Box::new(k)).fold()),
+            Some("split_at") => Ty::Tuple(vec![
+                Ty::View(Box::new(element.clone()), k.clone()),
+                Ty::View(Box::new(element.clone()), Nat::Binary('-', Box::new(n.clone()), Box::new(k)).fold())
+            ]),
+            _ => Ty::Unknown
+        }
+    }
+
+    fn closure_expr(&mut self, node: &SyntaxNode) -> Ty {
+        let params: Vec<Ty> = node.node(SyntaxKind::ClosureParamList).into_iter().flat_map(SyntaxNode::nodes).filter_map(Param::cast).map(|param| self.param(param)).collect();
+        if let Some(exec) = node.node(SyntaxKind::ExecAnnotation) {
+            let exec_ty = exec.node(SyntaxKind::ExecType).and_then(|exec_type| exec_type.tokens().find_map(|token| Exec::from_token(token.kind)));
+            self.definition_type(exec.node(SyntaxKind::Name), Ty::Exec(exec_ty.unwrap_or(Exec::Unknown)));
+        }
+        let ret_type = node.node(SyntaxKind::RetType).and_then(|ret_type| ret_type.nodes().next());
+        let declared = ret_type.map(|ret_type| self.lower(ret_type));
+        let body = node.nodes().find(|child| !matches!(child.kind, SyntaxKind::ClosureParamList | SyntaxKind::ExecAnnotation | SyntaxKind::RetType));
+        let ret = match (body, declared) {
+            (Some(body), Some(declared)) => {
+                self.check(body, &declared, ret_type.map(|ret_type| (ret_type.span.clone(), String::from("Return type declared here"))));
+                declared
+            },
+            (Some(body), None) => self.infer(body),
+            (None, declared) => declared.unwrap_or(Ty::Unknown)
+        };
+        Ty::Fn(params, Box::new(ret))
+    }
+
+    fn if_expr(&mut self, node: &SyntaxNode) -> Ty {
+        let mut then_ty = None;
+        let mut else_ty = None;
+        for child in node.nodes() {
+            match (child.kind, &then_ty) {
+                (SyntaxKind::Block, None) => then_ty = Some(self.block(child, None, None)),
+                (SyntaxKind::Block | SyntaxKind::IfExpr, Some(then_ty)) => {
+                    let then_ty = then_ty.clone();
+                    else_ty = Some(match child.kind {
+                        SyntaxKind::Block => self.block(child, Some(&then_ty), None),
+                        _ => self.check(child, &then_ty, None)
+                    });
+                },
+                _ => {
+                    self.check(child, &Ty::Scalar(Scalar::Bool), None);
+                }
+            }
+        }
+        // without else the value is ()
+        match else_ty {
+            Some(_) => then_ty.unwrap_or(Ty::Unknown),
+            None => Ty::unit()
+        }
+    }
+
+    fn for_expr(&mut self, node: &SyntaxNode) -> Ty {
+        let iterated = node.nodes().find(|child| !matches!(child.kind, SyntaxKind::Name | SyntaxKind::Block));
+        let element = match iterated {
+            Some(iterated) => {
+                let ty = self.infer(iterated);
+                let range = iterated.kind == SyntaxKind::BinaryExpr && iterated.token(TokenKind::DotDot).is_some();
+                match (range, ty.element()) {
+                    (true, _) => iterated.nodes().next().and_then(|start| self.types.expressions.get(&start.id)).cloned().unwrap_or(Ty::Unknown),
+                    (false, Some(element)) => element.clone(),
+                    (false, None) => {
+                        if !matches!(ty, Ty::Unknown | Ty::Generic(_)) {
+                            self.error(iterated.span.clone(), "invalid-operation", format!("Cannot iterate over a value of type `{ty}`"), None);
+                        }
+                        Ty::Unknown
+                    }
+                }
+            },
+            None => Ty::Unknown
+        };
+        self.definition_type(node.node(SyntaxKind::Name), element);
+        if let Some(block) = node.node(SyntaxKind::Block) {
+            self.block(block, None, None);
+        }
+        Ty::unit()
+    }
+}
+
+// Infers the types of a resolved syntax tree and checks that they fit together
+pub fn check(tree: &SyntaxTree, resolution: &Resolution) -> Types {
+    let mut checker = Checker { tree, resolution, types: Types::default(), structs: HashMap::new(), params: HashMap::new(), depth: Depth::default(), too_deep: false };
+    if let Some(file) = SourceFile::cast(&tree.root) {
+        checker.declarations(file);
+        for function in file.functions() {
+            checker.fn_decl(function);
+        }
+    }
+    checker.types.errors.sort_by_key(|error| error.span.start);
+    checker.types
+}
+
+#[test]
+fn test_check() {
+    let text = "struct Pair<n: nat> { fst: [f64; n], snd: (i32, bool) }
+
+fn scale<n: nat, r: prv>(vec: &r uniq gpu.global [f64; n], pair: Pair<16>) -[grid: gpu.grid<X<n/1024>, X<1024>>]-> f64 {
+    sched(X) block in grid {
+        sched(X) thread in block {
+            let v = &uniq (*vec).to_view.grp::<1024>[[block]][[thread]];
+            *v = *v * 3.0;
+            let x: u32 = 1;
+            let flag = pair.snd.1 && x < 2;
+        }
+    }
+    pair.fst[0]
+}
+";
+    let tree = crate::parser::parse(text);
+    assert_eq!(tree.errors, vec![]);
+    let resolution = crate::resolver::resolve(&tree);
+    let types = check(&tree, &resolution);
+    assert_eq!(types.errors, vec![]);
+
+    let type_of = |name: &str| {
+        let definition = resolution.definitions.iter().position(|definition| definition.name == name).unwrap();
+        types.definitions[&DefinitionId(definition as u32)].to_string()
+    };
+    assert_eq!(type_of("v"), "&uniq gpu.global f64");
+    assert_eq!(type_of("vec"), "&r uniq gpu.global [f64; n]");
+    assert_eq!(type_of("thread"), "gpu.thread");
+    assert_eq!(type_of("x"), "u32");
+    assert_eq!(type_of("flag"), "bool");
+    assert_eq!(type_of("scale"), "fn(&r uniq gpu.global [f64; n], Pair<16>) -> f64");
+
+    let offset = text.find("grp").unwrap();
+    let (span, description) = types.describe_at(&tree, &resolution, offset).unwrap();
+    assert_eq!(description, "[[[[f64; 1024]]; n/1024]]");
+    assert_eq!(&text[span], "(*vec).to_view.grp::<1024>");
+    let (_, description) = types.describe_at(&tree, &resolution, text.find("scale").unwrap()).unwrap();
+    assert_eq!(description, "fn scale<n: nat, r: prv>(vec: &r uniq gpu.global [f64; n], pair: Pair<16>) -[grid: gpu.grid<X<n/1024>, X<1024>>]-> f64");
+}
+
+#[test]
+fn test_check_errors() {
+    let text = "struct S { a: i32 }
+fn f(s: S, x: &shrd cpu.mem [i32; 4]) -> i32 {
+    let a: [i32; 3] = [1, 2, 3, 4];
+    let b = s.b + *x;
+    let c: bool = 1;
+    f(s);
+    let d = x[0];
+    s.a(1);
+}
+";
+    let tree = crate::parser::parse(text);
+    let resolution = crate::resolver::resolve(&tree);
+    let types = check(&tree, &resolution);
+    let errors: Vec<(&str, &str)> = types.errors.iter().map(|error| (&text[error.span.clone()], error.message.as_str())).collect();
+    assert_eq!(errors, vec![
+        ("[1, 2, 3, 4]", "Mismatched types: expected `[i32; 3]`, found `[i32; 4]`"),
+        ("s.b", "No field `b` on type `S`"),
+        ("1", "Mismatched types: expected `bool`, found `i32`"),
+        ("(s)", "Expected 2 arguments, found 1"),
+        ("x[0]", "Cannot index into a value of type `&shrd cpu.mem [i32; 4]`"),
+        ("s.a", "Expected a function, found `i32`"),
+        ("}", "Mismatched types: expected `i32`, found `()`")
+    ]);
+    // the missing value points at the return type
+    assert_eq!(types.errors[6].related.as_ref().map(|(span, _)| &text[span.clone()]), Some("i32"));
+}
+
+
+#[test]
+fn test_check_deep_nesting() {
+    // the parser cuts long operator chains off, the type checker only sees what's left of them
+    let chain = " + 1".repeat(100_000);
+    let text = format!("fn f() -> i32 {{ let x: i32 = 1{chain}; x }}\nfn g() -> i32 {{ let y: i32 = 1{chain}; }}\n");
+    let tree = crate::parser::parse(&text);
+    let messages: Vec<&str> = tree.errors.iter().map(|error| error.message.as_str()).collect();
+    assert_eq!(messages, vec!["Code is nested too deeply", "Code is nested too deeply"]);
+    let resolution = crate::resolver::resolve(&tree);
+    let types = check(&tree, &resolution);
+
+    // the body of g is missing its value, the error ends at the last token of the block
+    let errors: Vec<(&str, &str)> = types.errors.iter().map(|error| (&text[error.span.clone()], error.code)).collect();
+    assert_eq!(errors, vec![("}", "type-mismatch")]);
+
+    // hovers within and after the chain
+    let describe = |offset: usize| types.describe_at(&tree, &resolution, offset).map(|(_, description)| description);
+    assert_eq!(describe(text.find("1 + 1").unwrap() + 4 * 200).as_deref(), Some("i32"));
+    assert_eq!(describe(text.find("x }").unwrap()).as_deref(), Some("x: i32"));
+}
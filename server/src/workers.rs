@@ -0,0 +1,62 @@
+use std::{sync::{mpsc::{channel, Receiver, Sender}, Arc, Mutex}, thread::{self, JoinHandle}};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+// Fixed number of threads that take jobs from a shared queue, in the order they were submitted.
+// Dropping the pool closes the queue, the threads finish the remaining jobs and stop afterwards.
+pub struct WorkerPool {
+    jobs: Option<Sender<Job>>,
+    threads: Vec<JoinHandle<()>>
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> WorkerPool {
+        let (jobs, queue) = channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+
+        let threads = (0..size.max(1)).map(|i| {
+            let queue = queue.clone();
+            thread::Builder::new()
+                .name(format!("worker-{i}"))
+                .spawn(move || WorkerPool::work(&queue))
+                .expect("Error while spawning worker thread!")
+        }).collect();
+
+        WorkerPool { jobs: Some(jobs), threads }
+    }
+
+    // One worker per available core, as the jobs are mostly CPU bound
+    pub fn with_available_parallelism() -> WorkerPool {
+        WorkerPool::new(thread::available_parallelism().map(usize::from).unwrap_or(1))
+    }
+
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(jobs) = &self.jobs {
+            jobs.send(Box::new(job)).unwrap_or(());
+        }
+    }
+
+    fn work(queue: &Mutex<Receiver<Job>>) {
+        loop {
+            // only hold the lock while waiting for the next job, not while executing it
+            let job = match queue.lock() {
+                Ok(queue) => queue.recv(),
+                Err(_) => return
+            };
+            match job {
+                // jobs are expected to handle their panics, but a worker shouldn't get lost if they don't
+                Ok(job) => std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)).unwrap_or(()),
+                Err(_) => return // queue closed
+            }
+        }
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.jobs.take();
+        for thread in self.threads.drain(..) {
+            thread.join().unwrap_or(());
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use std::{collections::{HashMap, HashSet}, path::{Path, PathBuf}, sync::Arc};
+
+use url::Url;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{structures::PositionEncodingKind, TextDocument};
+
+pub const EXTENSION: &str = "desc";
+
+// On-disk view of the Descend files in the workspace folders. The documents open in the editor are
+// kept by the State and take precedence over the files here, see Snapshot::document.
+//
+// The files are shared with the snapshots, so cloning the workspace for a request is cheap. They are
+// copied on write if a snapshot still holds them.
+//
+// The folders are scanned on the worker pool, see scan. Files that are refreshed while their folder is
+// scanned keep what was refreshed, it's newer than what the scan read.
+#[derive(Debug, Clone, Default)]
+pub struct Workspace {
+    pub folders: Vec<String>, // uris of the workspace folders
+    pub files: Arc<HashMap<String, Arc<TextDocument>>>, // by uri
+    scanning: HashMap<String, HashSet<String>> // folders being scanned and the files refreshed meanwhile
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    Url::parse(uri).ok()?.to_file_path().ok()
+}
+
+// Uri of a file relative to a folder, built from the folder's uri so it's encoded like the client encodes it
+fn file_uri(folder: &Url, relative: &Path) -> Option<String> {
+    let mut uri = folder.clone();
+    uri.path_segments_mut().ok()?
+        .pop_if_empty()
+        .extend(relative.components().map(|component| component.as_os_str().to_string_lossy()));
+    Some(uri.to_string())
+}
+
+fn in_folder(folder: &str, uri: &str) -> bool {
+    uri.strip_prefix(folder.trim_end_matches('/')).is_some_and(|rest| rest.starts_with('/'))
+}
+
+// Hidden directories like ".git" don't contain sources
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.')
+}
+
+fn is_source(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == EXTENSION)
+}
+
+fn read(path: &Path, encoding: PositionEncodingKind) -> Option<Arc<TextDocument>> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => Some(Arc::new(TextDocument::new(&text, 0, encoding))),
+        Err(error) => {
+            eprintln!("Error while reading {}: {error}", path.display());
+            None
+        }
+    }
+}
+
+// Reads the Descend files of a folder, which takes a while for large folders so it's done on the worker pool
+pub fn scan(uri: &str, encoding: PositionEncodingKind) -> HashMap<String, Arc<TextDocument>> {
+    let mut files = HashMap::new();
+    let (Ok(folder), Some(root)) = (Url::parse(uri), uri_to_path(uri)) else {
+        eprintln!("Can't scan workspace folder \"{uri}\", it's not a local directory");
+        return files;
+    };
+    let entries = WalkDir::new(&root).into_iter().filter_entry(|entry| !is_hidden(entry)).filter_map(Result::ok);
+    for entry in entries {
+        if !entry.file_type().is_file() || !is_source(entry.path()) {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+        if let Some((uri, document)) = file_uri(&folder, relative).zip(read(entry.path(), encoding)) {
+            files.insert(uri, document);
+        }
+    }
+    files
+}
+
+impl Workspace {
+    // Adds the folder, returns whether it's new and has to be scanned
+    pub fn add_folder(&mut self, uri: &str) -> bool {
+        if self.folders.iter().any(|folder| folder == uri) {
+            return false;
+        }
+        self.folders.push(uri.to_string());
+        self.scanning.insert(uri.to_string(), HashSet::new());
+        true
+    }
+
+    // Adds the files the scan of the folder found, unless the folder was removed in the meantime
+    pub fn finish_scan(&mut self, uri: &str, scanned: HashMap<String, Arc<TextDocument>>) {
+        let Some(refreshed) = self.scanning.remove(uri) else {
+            return;
+        };
+        let files = Arc::make_mut(&mut self.files);
+        for (file, document) in scanned {
+            if !refreshed.contains(&file) {
+                files.entry(file).or_insert(document);
+            }
+        }
+    }
+
+    // Forgets the files of the folder, unless they are in another folder as well
+    pub fn remove_folder(&mut self, uri: &str) {
+        self.scanning.remove(uri);
+        self.folders.retain(|folder| folder != uri);
+        let folders = &self.folders;
+        Arc::make_mut(&mut self.files).retain(|file, _| folders.iter().any(|folder| in_folder(folder, file)));
+    }
+
+    // Whether the uri refers to a Descend file in one of the folders, no matter if it exists
+    pub fn contains(&self, uri: &str) -> bool {
+        self.folders.iter().any(|folder| in_folder(folder, uri)) && uri_to_path(uri).is_some_and(|path| is_source(&path))
+    }
+
+    // Rereads a file of the workspace from disk, or forgets it if it's gone
+    pub fn refresh(&mut self, uri: &str, encoding: PositionEncodingKind) {
+        if !self.contains(uri) {
+            return;
+        }
+        match uri_to_path(uri) {
+            Some(path) if path.is_file() => {
+                self.refreshed(uri);
+                if let Some(document) = read(&path, encoding) {
+                    Arc::make_mut(&mut self.files).insert(uri.to_string(), document);
+                }
+            },
+            _ => self.remove(uri)
+        }
+    }
+
+    // Forgets a file of the workspace that was deleted
+    pub fn remove(&mut self, uri: &str) {
+        if !self.contains(uri) {
+            return;
+        }
+        self.refreshed(uri);
+        Arc::make_mut(&mut self.files).remove(uri);
+    }
+
+    // The scans of the folders containing the file read an older state of it
+    fn refreshed(&mut self, uri: &str) {
+        for (folder, refreshed) in &mut self.scanning {
+            if in_folder(folder, uri) {
+                refreshed.insert(uri.to_string());
+            }
+        }
+    }
+}
+
+#[test]
+fn test_workspace() {
+    let root = std::env::temp_dir().join(format!("descend-workspace-{}", std::process::id()));
+    std::fs::create_dir_all(root.join("kernels")).unwrap();
+    std::fs::create_dir_all(root.join(".git")).unwrap();
+    std::fs::write(root.join("main.desc"), "fn main() {}").unwrap();
+    std::fs::write(root.join("kernels").join("scale vec.desc"), "fn scale() {}").unwrap();
+    std::fs::write(root.join("notes.txt"), "").unwrap();
+    std::fs::write(root.join(".git").join("stale.desc"), "").unwrap();
+
+    let folder = Url::from_directory_path(&root).unwrap().to_string();
+    let mut workspace = Workspace::default();
+    assert!(workspace.add_folder(&folder));
+    assert!(!workspace.add_folder(&folder));
+
+    // what's refreshed during the scan is newer than what the scan read
+    let scanned = scan(&folder, PositionEncodingKind::Utf16);
+    std::fs::write(root.join("main.desc"), "fn main() { }").unwrap();
+    workspace.refresh(&format!("{folder}main.desc"), PositionEncodingKind::Utf16);
+    workspace.finish_scan(&folder, scanned);
+
+    let mut files: Vec<&String> = workspace.files.keys().collect();
+    files.sort();
+    assert_eq!(files, vec![&format!("{folder}kernels/scale%20vec.desc"), &format!("{folder}main.desc")]);
+    assert_eq!(workspace.files[&format!("{folder}main.desc")].text(), "fn main() { }");
+
+    // snapshots share the files until the workspace changes
+    let snapshot = workspace.clone();
+    assert!(Arc::ptr_eq(&snapshot.files, &workspace.files));
+
+    std::fs::remove_file(root.join("main.desc")).unwrap();
+    workspace.refresh(&format!("{folder}main.desc"), PositionEncodingKind::Utf16);
+    assert_eq!(workspace.files.len(), 1);
+    assert_eq!(snapshot.files.len(), 2);
+
+    // deleted files are forgotten without looking at the disk
+    workspace.remove(&format!("{folder}kernels/scale%20vec.desc"));
+    assert!(workspace.files.is_empty());
+    workspace.refresh(&format!("{folder}kernels/scale%20vec.desc"), PositionEncodingKind::Utf16);
+    assert_eq!(workspace.files.len(), 1);
+
+    workspace.remove_folder(&folder);
+    assert!(workspace.files.is_empty());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,365 @@
+use std::{sync::mpsc::{channel, Receiver, Sender}, thread::{self, JoinHandle}, time::Duration};
+
+use descend_lsp::{serve, Message, ResponseMessage};
+use serde_json::json;
+
+fn message(value: serde_json::Value) -> Message {
+    serde_json::from_value(value).unwrap()
+}
+
+// Starts the server in-process and initializes it
+fn start() -> (Sender<Message>, Receiver<Message>, JoinHandle<i32>) {
+    start_with(json!({ "capabilities": {} }))
+}
+
+fn start_with(params: serde_json::Value) -> (Sender<Message>, Receiver<Message>, JoinHandle<i32>) {
+    let (client, incoming) = channel::<Message>();
+    let (outgoing, messages) = channel::<Message>();
+    let server = thread::spawn(move || serve(incoming, outgoing));
+
+    client.send(message(json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": params }))).unwrap();
+    assert!(response(&messages).result.is_some());
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }))).unwrap();
+
+    (client, messages, server)
+}
+
+fn stop(client: Sender<Message>, messages: Receiver<Message>, server: JoinHandle<i32>) {
+    client.send(message(json!({ "jsonrpc": "2.0", "id": 99, "method": "shutdown" }))).unwrap();
+    assert!(response(&messages).error.is_none());
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "exit" }))).unwrap();
+    assert_eq!(server.join().unwrap(), 0);
+}
+
+fn receive(messages: &Receiver<Message>) -> Message {
+    messages.recv_timeout(Duration::from_secs(5)).expect("Expected a message from the server")
+}
+
+fn is_diagnostics(message: &Message) -> bool {
+    matches!(message, Message::Notification(notification) if notification.method == "textDocument/publishDiagnostics")
+}
+
+// The next message, apart from diagnostics which are pushed whenever a document changes
+fn next(messages: &Receiver<Message>) -> Message {
+    loop {
+        let message = receive(messages);
+        if !is_diagnostics(&message) {
+            return message;
+        }
+    }
+}
+
+fn diagnostics(messages: &Receiver<Message>) -> serde_json::Value {
+    match receive(messages) {
+        Message::Notification(notification) if notification.method == "textDocument/publishDiagnostics" => notification.params,
+        message => panic!("Expected diagnostics, got {:?}", message)
+    }
+}
+
+fn response(messages: &Receiver<Message>) -> ResponseMessage {
+    match next(messages) {
+        Message::Response(response) => response,
+        message => panic!("Expected response, got {:?}", message)
+    }
+}
+
+fn open(client: &Sender<Message>, text: &str) {
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": { "uri": "file:///main.desc", "languageId": "descend", "version": 1, "text": text }
+    } }))).unwrap();
+}
+
+fn hover(client: &Sender<Message>, id: u64, line: u32, character: u32) {
+    client.send(message(json!({ "jsonrpc": "2.0", "id": id, "method": "textDocument/hover", "params": {
+        "textDocument": { "uri": "file:///main.desc" }, "position": { "line": line, "character": character }
+    } }))).unwrap();
+}
+
+#[test]
+fn test_in_process() {
+    let (client, messages, server) = start();
+
+    open(&client, "fn main() {}");
+    hover(&client, 1, 0, 3);
+    assert_eq!(response(&messages).result.unwrap()["contents"]["value"], "fn main()");
+
+    // changes without a range replace the whole document
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+        "textDocument": { "uri": "file:///main.desc", "version": 2 }, "contentChanges": [{ "text": "fn other() {}" }]
+    } }))).unwrap();
+    hover(&client, 2, 0, 3);
+    assert_eq!(response(&messages).result.unwrap()["contents"]["value"], "fn other()");
+
+    // params of the wrong shape don't take the server down
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "textDocument/didClose", "params": { "textDocument": 42 } }))).unwrap();
+    client.send(message(json!({ "jsonrpc": "2.0", "id": 3, "method": "textDocument/hover", "params": {
+        "textDocument": { "uri": "file:///main.desc" }, "position": { "line": "zero", "character": 3 }
+    } }))).unwrap();
+    let error = response(&messages).error.unwrap();
+    assert_eq!(error.code, -32602);
+    assert_eq!(error.data.unwrap()["path"], "position.line");
+
+    stop(client, messages, server);
+}
+
+#[test]
+fn test_handler_panics() {
+    let (client, messages, server) = start();
+
+    open(&client, "fn main() {}");
+    client.send(message(json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {
+        "textDocument": { "uri": "file:///unknown.desc" }, "position": { "line": 0, "character": 0 }
+    } }))).unwrap();
+    let error = response(&messages).error.unwrap();
+    assert_eq!(error.code, -32603);
+    assert_eq!(error.data.unwrap()["method"], "textDocument/hover");
+
+    // a failed edit leaves the document out of sync until it's reopened
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+        "textDocument": { "uri": "file:///main.desc", "version": 2 },
+        "contentChanges": [{ "range": { "start": { "line": 0, "character": 5 }, "end": { "line": 0, "character": 1 } }, "text": "" }]
+    } }))).unwrap();
+    assert!(matches!(next(&messages), Message::Notification(notification) if notification.method == "window/showMessage"));
+    hover(&client, 2, 0, 3);
+    assert_eq!(response(&messages).error.unwrap().code, -32801);
+
+    open(&client, "fn main() {}");
+    hover(&client, 3, 0, 3);
+    assert_eq!(response(&messages).result.unwrap()["contents"]["value"], "fn main()");
+
+    stop(client, messages, server);
+}
+
+#[test]
+fn test_document_versions() {
+    let (client, messages, server) = start();
+    let change = |version: i32, text: &str| message(json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+        "textDocument": { "uri": "file:///main.desc", "version": version }, "contentChanges": [{ "text": text }]
+    } }));
+
+    open(&client, "fn main() {}");
+    client.send(change(2, "fn other() {}")).unwrap();
+    hover(&client, 1, 0, 3);
+    assert_eq!(response(&messages).result.unwrap()["contents"]["value"], "fn other()");
+
+    // a duplicate change means the client and server disagree about the content
+    client.send(change(2, "fn third() {}")).unwrap();
+    assert!(matches!(next(&messages), Message::Notification(notification) if notification.method == "window/showMessage"));
+    hover(&client, 2, 0, 3);
+    assert_eq!(response(&messages).error.unwrap().code, -32801);
+
+    stop(client, messages, server);
+}
+
+#[test]
+fn test_full_sync() {
+    // the sync kind is advertised as requested by the client, incremental by default
+    let initialize = |params: serde_json::Value| {
+        let (client, incoming) = channel::<Message>();
+        let (outgoing, messages) = channel::<Message>();
+        let server = thread::spawn(move || serve(incoming, outgoing));
+        client.send(message(json!({ "jsonrpc": "2.0", "id": 0, "method": "initialize", "params": params }))).unwrap();
+        let result = response(&messages).result.unwrap();
+        client.send(message(json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }))).unwrap();
+        (client, messages, server, result)
+    };
+    let (client, messages, server, result) = initialize(json!({ "capabilities": {} }));
+    assert_eq!(result["capabilities"]["textDocumentSync"]["change"], 2);
+    stop(client, messages, server);
+    let (client, messages, server, result) = initialize(json!({ "capabilities": {}, "initializationOptions": { "textDocumentSync": 1 } }));
+    assert_eq!(result["capabilities"]["textDocumentSync"]["change"], 1);
+
+    // every change carries the whole text, without a range
+    let change = |version: i32, text: &str| message(json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+        "textDocument": { "uri": "file:///main.desc", "version": version }, "contentChanges": [{ "text": text }]
+    } }));
+    open(&client, "fn main() {}");
+    assert_eq!(diagnostics(&messages)["version"], 1);
+    client.send(change(2, "fn other() {}")).unwrap();
+    assert_eq!(diagnostics(&messages)["version"], 2);
+    client.send(change(3, "\nfn third() {")).unwrap();
+    let published = diagnostics(&messages);
+    assert_eq!(published["version"], 3);
+    assert_eq!(published["diagnostics"][0]["range"]["start"]["line"], 1);
+    hover(&client, 1, 1, 3);
+    assert_eq!(response(&messages).result.unwrap()["contents"]["value"], "fn third()");
+
+    stop(client, messages, server);
+}
+
+#[test]
+fn test_cancel_request() {
+    let (client, messages, server) = start();
+    let cancel = |id: u64| message(json!({ "jsonrpc": "2.0", "method": "$/cancelRequest", "params": { "id": id } }));
+
+    // the first hover analyses the whole document, it's still running when the cancellation arrives
+    let text: String = (0..5_000).map(|i| format!("fn f{i}(a: i32) -> i32 {{ let x: i32 = a + {i}; x }}\n")).collect();
+    open(&client, &text);
+    hover(&client, 1, 0, 3);
+    client.send(cancel(1)).unwrap();
+    let response1 = response(&messages);
+    assert_eq!(response1.result, None);
+    assert_eq!(response1.error.unwrap().code, -32800);
+
+    // answered and unknown requests aren't pending, cancelling them does nothing
+    hover(&client, 2, 0, 3);
+    assert_eq!(response(&messages).result.unwrap()["contents"]["value"], "fn f0(a: i32) -> i32");
+    client.send(cancel(2)).unwrap();
+    client.send(cancel(42)).unwrap();
+    hover(&client, 3, 1, 3);
+    assert_eq!(response(&messages).result.unwrap()["contents"]["value"], "fn f1(a: i32) -> i32");
+
+    stop(client, messages, server);
+}
+
+#[test]
+fn test_snapshots() {
+    let (client, messages, server) = start();
+
+    // the hover works on a snapshot while the change is applied, its answer would be outdated
+    let text: String = (0..5_000).map(|i| format!("fn f{i}(a: i32) -> i32 {{ let x: i32 = a + {i}; x }}\n")).collect();
+    open(&client, &text);
+    hover(&client, 1, 0, 3);
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+        "textDocument": { "uri": "file:///main.desc", "version": 2 }, "contentChanges": [{ "text": "fn changed() {}" }]
+    } }))).unwrap();
+    assert_eq!(response(&messages).error.unwrap().code, -32801);
+
+    hover(&client, 2, 0, 3);
+    assert_eq!(response(&messages).result.unwrap()["contents"]["value"], "fn changed()");
+
+    stop(client, messages, server);
+}
+
+#[test]
+fn test_watched_files() {
+    let root = std::env::temp_dir().join(format!("descend-watched-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    let uri = format!("file://{}/generated.desc", root.display());
+
+    let (client, messages, server) = start_with(json!({
+        "capabilities": { "workspace": { "didChangeWatchedFiles": { "dynamicRegistration": true } } },
+        "rootUri": format!("file://{}", root.display())
+    }));
+    let registration = match next(&messages) {
+        Message::Request(request) if request.method == "client/registerCapability" => request,
+        message => panic!("Expected registration, got {:?}", message)
+    };
+    assert_eq!(registration.params["registrations"][0]["registerOptions"]["watchers"][0]["globPattern"], "**/*.desc");
+    client.send(message(json!({ "jsonrpc": "2.0", "id": registration.id, "result": null }))).unwrap();
+
+    // files that show up on disk are read without being opened
+    std::fs::write(root.join("generated.desc"), "fn generated() {}").unwrap();
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "workspace/didChangeWatchedFiles", "params": {
+        "changes": [{ "uri": uri, "type": 1 }]
+    } }))).unwrap();
+    client.send(message(json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/hover", "params": {
+        "textDocument": { "uri": uri }, "position": { "line": 0, "character": 3 }
+    } }))).unwrap();
+    assert_eq!(response(&messages).result.unwrap()["contents"]["value"], "fn generated()");
+
+    stop(client, messages, server);
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_save() {
+    let (client, messages, server) = start();
+    let save = |text: Option<&str>| message(json!({ "jsonrpc": "2.0", "method": "textDocument/didSave", "params": {
+        "textDocument": { "uri": "file:///main.desc" }, "text": text
+    } }));
+
+    open(&client, "fn main() {}  \n");
+    assert_eq!(diagnostics(&messages)["diagnostics"], json!([]));
+    client.send(message(json!({ "jsonrpc": "2.0", "id": 1, "method": "textDocument/willSaveWaitUntil", "params": {
+        "textDocument": { "uri": "file:///main.desc" }, "reason": 1
+    } }))).unwrap();
+    assert_eq!(response(&messages).result.unwrap(), json!([]));
+
+    // the document is checked again on save
+    client.send(save(None)).unwrap();
+    assert_eq!(diagnostics(&messages)["version"], 1);
+
+    // the saved text wins over a document that lost track of it
+    client.send(save(Some("fn saved() {"))).unwrap();
+    assert_eq!(diagnostics(&messages)["diagnostics"][0]["message"], "Expected '}'");
+    hover(&client, 2, 0, 3);
+    assert_eq!(response(&messages).result.unwrap()["contents"]["value"], "fn saved()");
+
+    stop(client, messages, server);
+}
+
+#[test]
+fn test_diagnostics() {
+    let (client, messages, server) = start();
+
+    open(&client, "fn main() {\n    let x = ;\n");
+    let published = diagnostics(&messages);
+    assert_eq!(published["uri"], "file:///main.desc");
+    assert_eq!(published["version"], 1);
+    let problems = published["diagnostics"].as_array().unwrap();
+    assert_eq!(problems.len(), 2);
+    assert_eq!(problems[0]["range"]["start"], json!({ "line": 1, "character": 12 }));
+    assert_eq!(problems[0]["source"], "descend");
+    assert_eq!(problems[1]["relatedInformation"][0]["location"]["range"]["start"], json!({ "line": 0, "character": 10 }));
+
+    // fixing the document clears them
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {
+        "textDocument": { "uri": "file:///main.desc", "version": 2 }, "contentChanges": [{ "text": "fn main() {}" }]
+    } }))).unwrap();
+    let published = diagnostics(&messages);
+    assert_eq!(published["version"], 2);
+    assert_eq!(published["diagnostics"], json!([]));
+
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "textDocument/didClose", "params": { "textDocument": { "uri": "file:///main.desc" } } }))).unwrap();
+    let published = diagnostics(&messages);
+    assert_eq!(published["diagnostics"], json!([]));
+    assert!(published.get("version").is_none()); // left out rather than null
+
+    stop(client, messages, server);
+}
+
+#[test]
+fn test_pull_diagnostics() {
+    let root = std::env::temp_dir().join(format!("descend-pull-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("broken.desc"), "fn broken( {}").unwrap();
+    std::fs::write(root.join("main.desc"), "fn main() {}").unwrap();
+    let main = format!("file://{}/main.desc", root.display());
+
+    let (client, messages, server) = start_with(json!({
+        "capabilities": { "textDocument": { "diagnostic": {} }, "workspace": { "diagnostics": { "refreshSupport": true } } },
+        "rootUri": format!("file://{}", root.display())
+    }));
+    // the folder is scanned in the background, the client pulls again once its files are read
+    let refresh = match next(&messages) {
+        Message::Request(request) if request.method == "workspace/diagnostic/refresh" => request,
+        message => panic!("Expected refresh, got {:?}", message)
+    };
+    client.send(message(json!({ "jsonrpc": "2.0", "id": refresh.id, "result": null }))).unwrap();
+    client.send(message(json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {
+        "textDocument": { "uri": main, "languageId": "descend", "version": 1, "text": "fn main() {" }
+    } }))).unwrap();
+
+    let pull = |id: u64, previous_result_id: Option<&str>| message(json!({ "jsonrpc": "2.0", "id": id, "method": "textDocument/diagnostic", "params": {
+        "textDocument": { "uri": main }, "previousResultId": previous_result_id
+    } }));
+    client.send(pull(1, None)).unwrap();
+    let report = response(&messages).result.unwrap();
+    assert_eq!(report["kind"], "full");
+    assert_eq!(report["items"][0]["message"], "Expected '}'");
+    client.send(pull(2, report["resultId"].as_str())).unwrap();
+    assert_eq!(response(&messages).result.unwrap(), json!({ "kind": "unchanged", "resultId": report["resultId"] }));
+
+    // the open document is left out, its file on disk isn't what the user sees
+    client.send(message(json!({ "jsonrpc": "2.0", "id": 3, "method": "workspace/diagnostic", "params": { "previousResultIds": [] } }))).unwrap();
+    let report = response(&messages).result.unwrap();
+    let items = report["items"].as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert!(items[0]["uri"].as_str().unwrap().ends_with("/broken.desc"));
+    assert_eq!(items[0]["version"], serde_json::Value::Null);
+    assert_eq!(items[0]["kind"], "full");
+
+    stop(client, messages, server);
+    std::fs::remove_dir_all(&root).unwrap();
+}